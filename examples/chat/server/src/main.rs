@@ -1,28 +1,37 @@
 use anyhow::Result;
-use axum::{response::Html, response::IntoResponse, routing::get, routing::post, Json, Router, http::{Method, header, Response, StatusCode, HeaderValue}, body::{self, Full}};
+use axum::{response::Html, response::IntoResponse, routing::get, routing::post, Extension, Json, Router, http::{Method, header, Response, StatusCode, HeaderValue}, body::{self, Full}};
 use cyberdeck::*;
-use tokio::time::{sleep, Duration};
 use tower_http::cors::{Any, CorsLayer};
-use std::{net::SocketAddr, sync::Arc};
-use dashmap::{DashMap, DashSet};
-use once_cell::sync::Lazy;
+use std::net::SocketAddr;
 
-static CLIENTS: Lazy<DashMap<u128, DashMap<String, Arc<RTCDataChannel>>>> = Lazy::new(|| {
-    DashMap::new()
-});
-
-static ROOMS: Lazy<DashMap<String, DashSet<u128>>> = Lazy::new(|| {
-    DashMap::new()
-});
+#[derive(Clone)]
+struct Hub {
+    manager: PeerManager,
+    rooms: Rooms,
+}
 
 #[tokio::main]
 async fn main() {
+    let (manager, events) = PeerManager::new();
+    let (rooms, mut room_events) = Rooms::new(&manager);
+    let hub = Hub { manager, rooms };
+    tokio::spawn(handle_events(hub.clone(), events));
+    tokio::spawn(async move {
+        while let Some(e) = room_events.next().await {
+            match e {
+                RoomEvent::Joined { room, peer_id } => println!("{}::Joined room {}", peer_id, room),
+                RoomEvent::Left { room, peer_id } => println!("{}::Left room {}", peer_id, room),
+            }
+        }
+    });
+
     // build our application with a route
     let app = Router::new()
         .route("/", get(root))
         .route("/pkg/client_wasm.js", get(js))
         .route("/pkg/client_wasm_bg.wasm", get(wasm))
         .route("/connect", post(connect))
+        .layer(Extension(hub))
 	.layer(CorsLayer::new()
         // allow `GET` and `POST` when accessing the resource
         .allow_methods([Method::GET, Method::POST])
@@ -38,19 +47,28 @@ async fn main() {
         .unwrap();
 }
 
-async fn connect(Json(offer): Json<String>) -> impl IntoResponse {
-    match start_peer_connection(offer).await {
+async fn connect(Extension(hub): Extension<Hub>, Json(offer): Json<String>) -> impl IntoResponse {
+    match start_peer_connection(&hub.manager, offer).await {
         Ok(answer) => Ok(Json(answer)),
         Err(_) => Err("failed to connect"),
     }
 }
 
-async fn start_peer_connection(offer: String) -> Result<String> {
-    let mut peer = Peer::new(|peer_id, e| async move {
-        if !CLIENTS.contains_key(&peer_id) {
-            CLIENTS.insert(peer_id, DashMap::new());
-        }
+async fn start_peer_connection(manager: &PeerManager, offer: String) -> Result<String> {
+    let (mut peer, events) = Peer::new_with_stream(
+        ConfigurationBuilder::new().stun_server(DEFAULT_STUN_URL).build(),
+    )
+    .await?;
+    let answer = peer.receive_offer(&offer).await?;
 
+    // the manager keeps the peer alive until its connection is closed or fails
+    manager.insert(peer, events);
+
+    Ok(answer)
+}
+
+async fn handle_events(hub: Hub, mut events: PeerManagerEvents) {
+    while let Some((peer_id, e)) = events.next().await {
         match e {
             PeerEvent::DataChannelMessage(c, m) => {
                 println!(
@@ -68,34 +86,18 @@ async fn start_peer_connection(offer: String) -> Result<String> {
                 );
 
                 // Lobby channel handles specific commands only
-                if c.label() == "lobby" {
-                    if msg_str.starts_with("/join") {
-                        let room_name = msg_str.split(" ").nth(1);
-
-                        if let Some(room_name) = room_name {
-                            if !ROOMS.contains_key(room_name) {
-                                ROOMS.insert(room_name.to_owned(), DashSet::new());
-                            }
-
-                            ROOMS.get_mut(room_name).unwrap().insert(peer_id);
-                        } else {
-                            println!("{}::Invalid attempt to join room", peer_id);
-                        }
+                if c.label() == "lobby" && msg_str.starts_with("/join") {
+                    if let Some(room_name) = msg_str.split(' ').nth(1) {
+                        hub.rooms.join(room_name, peer_id);
+                    } else {
+                        println!("{}::Invalid attempt to join room", peer_id);
                     }
                 }
 
-                // Send to room participants
-                if let Some(room) = ROOMS.get(c.label()){ 
-                    for client_id in room.iter() {
-                        if let Some(channel) = CLIENTS.get(&client_id).and_then(| client | {
-                            client.get(c.label()).and_then(|channel| {
-                                Some(channel.clone())
-                            })
-                        }) {
-                            channel.send_text(format!("{}: {}", peer_id, msg_str)).await;
-                        }
-                    }
-                }
+                // Send to room participants, on the channel named after the room
+                hub.rooms
+                    .publish_text(c.label(), Some(peer_id), c.label(), &format!("{}: {}", peer_id, msg_str))
+                    .await;
             }
             PeerEvent::DataChannelStateChange(c) => {
                 if c.ready_state() == RTCDataChannelState::Open {
@@ -103,40 +105,23 @@ async fn start_peer_connection(offer: String) -> Result<String> {
                     c.send_text("Connected to client!".to_string())
                         .await
                         .unwrap();
-                    
-                    CLIENTS.get(&peer_id).unwrap().value().insert(c.label().to_owned(), c.clone());
                 } else if c.ready_state() == RTCDataChannelState::Closed {
                     println!("{}::DataChannel '{}'", peer_id, c.label());
                 }
             }
             PeerEvent::PeerConnectionStateChange(s) => {
-                match s {
-                    RTCPeerConnectionState::Closed | RTCPeerConnectionState::Disconnected | RTCPeerConnectionState::Failed => {
-                        CLIENTS.remove(&peer_id);
-                    },
-                    _ => {}
-                }
                 println!("{}::Peer connection state: {} ", peer_id, s);
+                // a closed tab only shows up as disconnected, removing the peer also takes it out of its rooms
+                if s == RTCPeerConnectionState::Disconnected || s == RTCPeerConnectionState::Failed {
+                    let manager = hub.manager.clone();
+                    tokio::spawn(async move {
+                        let _ = manager.remove(peer_id).await;
+                    });
+                }
             }
+            _ => {}
         }
-    }, None)
-    .await?;
-
-    let answer = peer.receive_offer(&offer).await?;
-
-    // move cyberdeck to another thread to keep it alive
-    tokio::spawn(async move {
-        while peer.connection_state() != RTCPeerConnectionState::Closed
-            && peer.connection_state() != RTCPeerConnectionState::Disconnected
-            && peer.connection_state() != RTCPeerConnectionState::Failed
-        {
-            // keep the connection alive while not in invalid state
-            sleep(Duration::from_millis(1000)).await;
-        }
-        // because we moved cyberdeck ownership into here gets dropped here and will stop all channels
-    });
-
-    Ok(answer)
+    }
 }
 
 // basic handler that responds with a static string
@@ -0,0 +1,43 @@
+use anyhow::Result;
+use cyberdeck::signaling::http_client::HttpClient;
+use cyberdeck::*;
+use std::time::Duration;
+
+// A headless client for `cargo run --example signaling_server --features axum`:
+// cargo run --example http_client --features http-client
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut peer = Peer::new(
+        |peer_id, e| async move {
+            match e {
+                PeerEvent::DataChannelMessage(c, m) => {
+                    let msg_str = String::from_utf8(m.data.to_vec()).unwrap();
+                    println!("{}::Message from DataChannel '{}': {}", peer_id, c.label(), msg_str);
+                }
+                PeerEvent::DataChannelStateChange(c)
+                    if c.ready_state() == RTCDataChannelState::Open =>
+                {
+                    c.send_text("Hello from a bot!".to_string()).await.unwrap();
+                }
+                PeerEvent::PeerConnectionStateChange(s) => {
+                    println!("{}::Peer connection state: {} ", peer_id, s)
+                }
+                _ => {}
+            }
+        },
+        None,
+    )
+    .await?;
+    peer.create_channel("bot").await?;
+
+    HttpClient::new("http://localhost:3000/connect")
+        .header("User-Agent", "cyberdeck-bot")
+        .timeout(Duration::from_secs(10))
+        .retries(3, Duration::from_secs(1))
+        .connect(&mut peer)
+        .await?;
+
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    peer.close().await?;
+    Ok(())
+}
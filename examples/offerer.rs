@@ -0,0 +1,62 @@
+use anyhow::Result;
+use cyberdeck::*;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut config = ConfigurationBuilder::new().stun_server(DEFAULT_STUN_URL);
+    // `--token` exchanges short connection tokens instead of the full base64 descriptions
+    if std::env::args().any(|arg| arg == "--token") {
+        config = config.sdp_codec(TokenCodec);
+    }
+    let mut peer = Peer::new_with_configuration(|peer_id, e| async move {
+        match e {
+            PeerEvent::DataChannelMessage(c, m) => {
+                let msg_str = String::from_utf8(m.data.to_vec()).unwrap();
+                println!(
+                    "{}::Message from DataChannel '{}': {}",
+                    peer_id,
+                    c.label(),
+                    msg_str
+                );
+            }
+            PeerEvent::DataChannelStateChange(c) => {
+                if c.ready_state() == RTCDataChannelState::Open {
+                    println!("{}::DataChannel '{}' open", peer_id, c.label());
+                    c.send_text("Hello from the offering side!".to_string())
+                        .await
+                        .unwrap();
+                } else if c.ready_state() == RTCDataChannelState::Closed {
+                    println!("{}::DataChannel '{}' closed", peer_id, c.label());
+                }
+            }
+            PeerEvent::PeerConnectionStateChange(s) => {
+                println!("{}::Peer connection state: {} ", peer_id, s)
+            }
+            _ => {}
+        }
+    }, config.build())
+    .await?;
+
+    // the channel has to exist before the offer so it is negotiated
+    peer.create_channel("foo").await?;
+    let offer = peer.create_offer().await?;
+    println!("Paste this offer into the receiver example: {}", offer);
+
+    println!("Then paste the answer here:");
+    let answer = must_read_stdin()?;
+    peer.receive_answer(&answer).await?;
+
+    tokio::signal::ctrl_c().await?;
+    peer.close().await?;
+    Ok(())
+}
+
+pub fn must_read_stdin() -> Result<String> {
+    let mut line = String::new();
+
+    std::io::stdin().read_line(&mut line)?;
+    line = line.trim().to_owned();
+    println!();
+
+    Ok(line)
+}
@@ -4,7 +4,12 @@ use cyberdeck::*;
 #[tokio::main]
 async fn main() -> Result<()> {
     let offer = must_read_stdin()?;
-    let mut peer = Peer::new(|peer_id, e| async move {
+    let mut config = ConfigurationBuilder::new().stun_server(DEFAULT_STUN_URL);
+    // `--token` exchanges short connection tokens instead of the full base64 descriptions
+    if std::env::args().any(|arg| arg == "--token") {
+        config = config.sdp_codec(TokenCodec);
+    }
+    let mut peer = Peer::new_with_configuration(|peer_id, e| async move {
         match e {
             PeerEvent::DataChannelMessage(c, m) => {
                 println!(
@@ -34,8 +39,9 @@ async fn main() -> Result<()> {
             PeerEvent::PeerConnectionStateChange(s) => {
                 println!("{}::Peer connection state: {} ", peer_id, s)
             }
+            _ => {}
         }
-    })
+    }, config.build())
     .await?;
     let answer = peer.receive_offer(&offer).await?;
 
@@ -0,0 +1,70 @@
+use anyhow::Result;
+use cyberdeck::signaling::websocket::WebSocketSignaling;
+use cyberdeck::signaling::SignalMessage;
+use cyberdeck::*;
+
+// Start `cargo run --example signaling_server --features axum`, then run this twice:
+// cargo run --example room_peer --features websocket -- lobby
+#[tokio::main]
+async fn main() -> Result<()> {
+    let room = std::env::args().nth(1).unwrap_or("lobby".to_string());
+    let mut signaling = WebSocketSignaling::connect("ws://localhost:3000/ws").await?;
+    let peers = signaling.join(&room).await?;
+    println!("joined {} as {}", room, signaling.id().unwrap());
+
+    let config = ConfigurationBuilder::new()
+        .stun_server(DEFAULT_STUN_URL)
+        .trickle_ice(true)
+        .build();
+    let (mut peer, mut events) = Peer::new_with_stream(config).await?;
+
+    // whoever joins last calls the peer that is already waiting
+    let mut remote = peers.first().cloned();
+    if let Some(remote) = &remote {
+        peer.create_channel("chat").await?;
+        let sdp = peer.create_offer().await?;
+        signaling
+            .send(&SignalMessage::Offer {
+                peer: Some(remote.clone()),
+                sdp,
+            })
+            .await?;
+    }
+
+    loop {
+        tokio::select! {
+            message = signaling.recv() => match message? {
+                Some(SignalMessage::PeerJoined { id }) if remote.is_none() => remote = Some(id),
+                Some(SignalMessage::Offer { peer: from, sdp }) => {
+                    remote = from;
+                    let sdp = peer.receive_offer(&sdp).await?;
+                    signaling.send(&SignalMessage::Answer { peer: remote.clone(), sdp }).await?;
+                }
+                Some(SignalMessage::Answer { sdp, .. }) => peer.receive_answer(&sdp).await?,
+                Some(SignalMessage::Candidate { candidate, .. }) => peer.add_ice_candidate(&candidate).await?,
+                Some(SignalMessage::Error { message }) => println!("signaling error: {}", message),
+                Some(_) => {}
+                None => break,
+            },
+            event = events.next() => match event {
+                Some(PeerEvent::IceCandidate(candidate)) => {
+                    signaling.send(&SignalMessage::Candidate { peer: remote.clone(), candidate }).await?;
+                }
+                Some(PeerEvent::DataChannelStateChange(c)) => {
+                    if c.ready_state() == RTCDataChannelState::Open {
+                        c.send_text(format!("hello from {}", peer.peer_id)).await?;
+                    }
+                }
+                Some(PeerEvent::DataChannelMessage(c, m)) => {
+                    println!("{}: {}", c.label(), String::from_utf8_lossy(&m.data));
+                }
+                Some(PeerEvent::PeerConnectionStateChange(s)) => println!("Peer connection state: {}", s),
+                Some(_) => {}
+                None => break,
+            },
+        }
+    }
+
+    peer.close().await?;
+    Ok(())
+}
@@ -1,90 +1,70 @@
-use anyhow::Result;
-use axum::{response::Html, response::IntoResponse, routing::get, routing::post, Json, Router, http::{Method, header}};
+use axum::{response::Html, response::IntoResponse, routing::get, Router};
+use cyberdeck::signaling::server::SignalingServer;
 use cyberdeck::*;
 use std::net::SocketAddr;
-use tokio::time::{sleep, Duration};
-
-//use tower::{ServiceBuilder, ServiceExt, Service};
-use tower_http::cors::{Any, CorsLayer};
 
 #[tokio::main]
 async fn main() {
+    let signaling = SignalingServer::new(handle_event);
+    let shutdown = signaling.shutdown_handle();
+
     // build our application with a route
     let app = Router::new()
         .route("/", get(root))
-        .route("/connect", post(connect))
-	.layer(CorsLayer::new()
-        // allow `GET` and `POST` when accessing the resource
-        .allow_methods([Method::GET, Method::POST])
-        // allow requests from any origin
-        .allow_origin(Any)
-	.allow_headers([header::CONTENT_TYPE]));
+        .merge(signaling.router());
 
     let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
     println!("Running server on http://localhost:3000 ...");
     axum::Server::bind(&addr)
         .serve(app.into_make_service())
+        .with_graceful_shutdown(async move {
+            tokio::signal::ctrl_c().await.unwrap();
+            // say goodbye to every peer before the server stops
+            let report = shutdown
+                .shutdown(&ShutdownOptions::new().goodbye("Server is shutting down"))
+                .await;
+            println!(
+                "Closed {} of {} peers cleanly",
+                report.closed_cleanly, report.peers
+            );
+        })
         .await
         .unwrap();
 }
 
-async fn connect(Json(offer): Json<String>) -> impl IntoResponse {
-    match start_peer_connection(offer).await {
-        Ok(answer) => Ok(Json(answer)),
-        Err(_) => Err("failed to connect"),
-    }
-}
-
-async fn start_peer_connection(offer: String) -> Result<String> {
-    let mut peer = Peer::new(|peer_id, e| async move {
-        match e {
-            PeerEvent::DataChannelMessage(c, m) => {
-                println!(
-                    "{}::Recieved a message from channel {} with id {}!",
-                    peer_id,
-                    c.label(),
-                    c.id()
-                );
-                let msg_str = String::from_utf8(m.data.to_vec()).unwrap();
-                println!(
-                    "{}::Message from DataChannel '{}': {}",
-                    peer_id,
-                    c.label(),
-                    msg_str
-                );
-                c.send_text(format!("Echo {}", msg_str)).await.unwrap();
-            }
-            PeerEvent::DataChannelStateChange(c) => {
-                if c.ready_state() == RTCDataChannelState::Open {
-                    println!("{}::DataChannel '{}'", peer_id, c.label());
-                    c.send_text("Connected to client!".to_string())
-                        .await
-                        .unwrap();
-                } else if c.ready_state() == RTCDataChannelState::Closed {
-                    println!("{}::DataChannel '{}'", peer_id, c.label());
-                }
-            }
-            PeerEvent::PeerConnectionStateChange(s) => {
-                println!("{}::Peer connection state: {} ", peer_id, s)
+async fn handle_event(peer_id: u128, e: PeerEvent) {
+    match e {
+        PeerEvent::DataChannelMessage(c, m) => {
+            println!(
+                "{}::Recieved a message from channel {} with id {}!",
+                peer_id,
+                c.label(),
+                c.id()
+            );
+            let msg_str = String::from_utf8(m.data.to_vec()).unwrap();
+            println!(
+                "{}::Message from DataChannel '{}': {}",
+                peer_id,
+                c.label(),
+                msg_str
+            );
+            c.send_text(format!("Echo {}", msg_str)).await.unwrap();
+        }
+        PeerEvent::DataChannelStateChange(c) => {
+            if c.ready_state() == RTCDataChannelState::Open {
+                println!("{}::DataChannel '{}'", peer_id, c.label());
+                c.send_text("Connected to client!".to_string())
+                    .await
+                    .unwrap();
+            } else if c.ready_state() == RTCDataChannelState::Closed {
+                println!("{}::DataChannel '{}'", peer_id, c.label());
             }
         }
-    })
-    .await?;
-    let answer = peer.receive_offer(&offer).await?;
-
-    // move cyberdeck to another thread to keep it alive
-    tokio::spawn(async move {
-        while peer.connection_state() != RTCPeerConnectionState::Closed
-            && peer.connection_state() != RTCPeerConnectionState::Disconnected
-            && peer.connection_state() != RTCPeerConnectionState::Failed
-        {
-            // keep the connection alive while not in invalid state
-            sleep(Duration::from_millis(1000)).await;
+        PeerEvent::PeerConnectionStateChange(s) => {
+            println!("{}::Peer connection state: {} ", peer_id, s)
         }
-        // because we moved cyberdeck ownership into here gets dropped here and will stop all channels
-    });
-
-    Ok(answer)
+        _ => {}
+    }
 }
 
 // basic handler that responds with a static string
@@ -0,0 +1,136 @@
+use crate::{Error, Result};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+
+/// Turns the JSON of session descriptions and ICE candidates into strings for signaling and back
+pub trait SdpCodec: Send + Sync {
+    fn encode(&self, json: &str) -> Result<String>;
+    fn decode(&self, encoded: &str) -> Result<String>;
+}
+
+/// Standard padded base64 of the JSON, the default and what the web client expects
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Base64Codec;
+
+impl SdpCodec for Base64Codec {
+    fn encode(&self, json: &str) -> Result<String> {
+        Ok(STANDARD.encode(json))
+    }
+
+    fn decode(&self, encoded: &str) -> Result<String> {
+        let b = STANDARD.decode(encoded.trim())?;
+        Ok(String::from_utf8(b)?)
+    }
+}
+
+/// The JSON as is
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonCodec;
+
+impl SdpCodec for JsonCodec {
+    fn encode(&self, json: &str) -> Result<String> {
+        Ok(json.to_owned())
+    }
+
+    fn decode(&self, encoded: &str) -> Result<String> {
+        Ok(encoded.to_owned())
+    }
+}
+
+/// URL-safe base64 without padding, usable in query strings and QR codes
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UrlSafeBase64Codec;
+
+impl SdpCodec for UrlSafeBase64Codec {
+    fn encode(&self, json: &str) -> Result<String> {
+        Ok(URL_SAFE_NO_PAD.encode(json))
+    }
+
+    fn decode(&self, encoded: &str) -> Result<String> {
+        let b = URL_SAFE_NO_PAD.decode(encoded.trim())?;
+        Ok(String::from_utf8(b)?)
+    }
+}
+
+/// Deflate compressed JSON as URL-safe base64, for offers that are pasted by hand
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DeflateCodec;
+
+impl SdpCodec for DeflateCodec {
+    fn encode(&self, json: &str) -> Result<String> {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+        encoder
+            .write_all(json.as_bytes())
+            .and_then(|_| encoder.finish())
+            .map(|b| URL_SAFE_NO_PAD.encode(b))
+            .map_err(|e| Error::SignalingEncode(e.to_string()))
+    }
+
+    fn decode(&self, encoded: &str) -> Result<String> {
+        let b = URL_SAFE_NO_PAD.decode(encoded.trim())?;
+        let mut json = String::new();
+        DeflateDecoder::new(&b[..])
+            .read_to_string(&mut json)
+            .map_err(|e| Error::SignalingDecode(e.to_string()))?;
+        Ok(json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESCRIPTION: &str = r#"{"type":"offer","sdp":"v=0\r\no=- 1 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\na=group:BUNDLE 0\r\nm=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\nc=IN IP4 0.0.0.0\r\na=setup:actpass\r\na=mid:0\r\na=sctp-port:5000\r\n"}"#;
+
+    fn codecs() -> Vec<Box<dyn SdpCodec>> {
+        vec![
+            Box::new(Base64Codec),
+            Box::new(JsonCodec),
+            Box::new(UrlSafeBase64Codec),
+            Box::new(DeflateCodec),
+        ]
+    }
+
+    #[test]
+    fn roundtrip() {
+        for codec in codecs() {
+            let encoded = codec.encode(DESCRIPTION).unwrap();
+            assert_eq!(codec.decode(&encoded).unwrap(), DESCRIPTION);
+            // pasted strings often come with a trailing newline
+            assert_eq!(codec.decode(&format!("{}\n", encoded)).unwrap().trim(), DESCRIPTION);
+        }
+    }
+
+    #[test]
+    fn url_safe_codecs_need_no_escaping() {
+        for encoded in [
+            UrlSafeBase64Codec.encode(DESCRIPTION).unwrap(),
+            DeflateCodec.encode(DESCRIPTION).unwrap(),
+        ] {
+            assert!(encoded
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        }
+    }
+
+    #[test]
+    fn deflate_is_shorter() {
+        assert!(
+            DeflateCodec.encode(DESCRIPTION).unwrap().len()
+                < Base64Codec.encode(DESCRIPTION).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn garbage_fails_to_decode() {
+        assert!(Base64Codec.decode("not base64!").is_err());
+        assert!(UrlSafeBase64Codec.decode("not+base64/").is_err());
+        // valid base64, but not deflate
+        let not_deflate = UrlSafeBase64Codec.encode(DESCRIPTION).unwrap();
+        assert!(DeflateCodec.decode(&not_deflate).is_err());
+    }
+}
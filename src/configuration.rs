@@ -0,0 +1,238 @@
+use crate::heartbeat::Heartbeat;
+use crate::negotiation::Negotiation;
+use crate::reconnect::Reconnect;
+use crate::{
+    Base64Codec, BackpressurePolicy, DispatchMode, NegotiationRole, ReconnectPolicy, Result,
+    SdpCodec,
+};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use webrtc::ice_transport::ice_credential_type::RTCIceCredentialType;
+pub use webrtc::ice_transport::ice_server::RTCIceServer;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+pub use webrtc::peer_connection::policy::bundle_policy::RTCBundlePolicy;
+pub use webrtc::peer_connection::policy::ice_transport_policy::RTCIceTransportPolicy;
+
+pub const DEFAULT_STUN_URL: &str = "stun:stun.l.google.com:19302";
+
+/// ICE setup used when creating the underlying RTCPeerConnection, see `ConfigurationBuilder`
+#[derive(Clone)]
+pub struct Configuration {
+    ice_servers: Vec<RTCIceServer>,
+    ice_transport_policy: RTCIceTransportPolicy,
+    ice_candidate_pool_size: u8,
+    bundle_policy: RTCBundlePolicy,
+    pub(crate) trickle_ice: bool,
+    pub(crate) event_queue_capacity: Option<usize>,
+    pub(crate) backpressure_policy: BackpressurePolicy,
+    pub(crate) dispatch_mode: DispatchMode,
+    pub(crate) sdp_codec: Arc<dyn SdpCodec>,
+    pub(crate) reconnect: Option<Reconnect>,
+    pub(crate) negotiation: Option<Negotiation>,
+    pub(crate) stats_interval: Option<Duration>,
+    pub(crate) heartbeat: Option<Heartbeat>,
+    pub(crate) idle_timeout: Option<Duration>,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Configuration {
+            ice_servers: Vec::new(),
+            ice_transport_policy: RTCIceTransportPolicy::default(),
+            ice_candidate_pool_size: 0,
+            bundle_policy: RTCBundlePolicy::default(),
+            trickle_ice: false,
+            event_queue_capacity: None,
+            backpressure_policy: BackpressurePolicy::Block,
+            dispatch_mode: DispatchMode::Serial,
+            sdp_codec: Arc::new(Base64Codec),
+            reconnect: None,
+            negotiation: None,
+            stats_interval: None,
+            heartbeat: None,
+            idle_timeout: None,
+        }
+    }
+}
+
+impl Configuration {
+    pub fn builder() -> ConfigurationBuilder {
+        ConfigurationBuilder::new()
+    }
+
+    pub(crate) fn to_rtc_configuration(&self) -> RTCConfiguration {
+        RTCConfiguration {
+            ice_servers: self.ice_servers.clone(),
+            ice_transport_policy: self.ice_transport_policy,
+            ice_candidate_pool_size: self.ice_candidate_pool_size,
+            bundle_policy: self.bundle_policy,
+            ..Default::default()
+        }
+    }
+}
+
+/// Builds a `Configuration` for `Peer::new_with_configuration`
+#[derive(Default)]
+pub struct ConfigurationBuilder {
+    config: Configuration,
+}
+
+impl ConfigurationBuilder {
+    pub fn new() -> ConfigurationBuilder {
+        ConfigurationBuilder::default()
+    }
+
+    /// Add a STUN server without credentials
+    pub fn stun_server(self, url: &str) -> ConfigurationBuilder {
+        self.ice_server(RTCIceServer {
+            urls: vec![url.to_owned()],
+            ..Default::default()
+        })
+    }
+
+    /// Add a TURN server authenticated with a username and password
+    pub fn turn_server(self, url: &str, username: &str, credential: &str) -> ConfigurationBuilder {
+        self.ice_server(RTCIceServer {
+            urls: vec![url.to_owned()],
+            username: username.to_owned(),
+            credential: credential.to_owned(),
+            credential_type: RTCIceCredentialType::Password,
+        })
+    }
+
+    /// Add an arbitrary ICE server entry, each entry keeps its own credentials
+    pub fn ice_server(mut self, server: RTCIceServer) -> ConfigurationBuilder {
+        self.config.ice_servers.push(server);
+        self
+    }
+
+    /// Restrict which candidates may be used, e.g. `RTCIceTransportPolicy::Relay` for TURN only
+    pub fn ice_transport_policy(mut self, policy: RTCIceTransportPolicy) -> ConfigurationBuilder {
+        self.config.ice_transport_policy = policy;
+        self
+    }
+
+    pub fn ice_candidate_pool_size(mut self, size: u8) -> ConfigurationBuilder {
+        self.config.ice_candidate_pool_size = size;
+        self
+    }
+
+    pub fn bundle_policy(mut self, policy: RTCBundlePolicy) -> ConfigurationBuilder {
+        self.config.bundle_policy = policy;
+        self
+    }
+
+    /// Return offers/answers without waiting for ICE gathering, local candidates are
+    /// delivered as `PeerEvent::IceCandidate` and remote ones go to `Peer::add_ice_candidate`
+    pub fn trickle_ice(mut self, enabled: bool) -> ConfigurationBuilder {
+        self.config.trickle_ice = enabled;
+        self
+    }
+
+    /// Bound the number of undelivered events, by default the queue grows without limit.
+    /// The same bound applies to each channel's queue under `DispatchMode::PerChannel`.
+    /// Panics if `capacity` is 0, a queue has to hold at least one event.
+    pub fn event_queue(mut self, capacity: usize, policy: BackpressurePolicy) -> ConfigurationBuilder {
+        assert!(capacity > 0, "event queue capacity must be at least 1");
+        self.config.event_queue_capacity = Some(capacity);
+        self.config.backpressure_policy = policy;
+        self
+    }
+
+    /// Choose how the `handle_message` callback is driven, see `DispatchMode`
+    pub fn dispatch_mode(mut self, mode: DispatchMode) -> ConfigurationBuilder {
+        self.config.dispatch_mode = mode;
+        self
+    }
+
+    /// Wire format of offers, answers and candidates, `Base64Codec` by default.
+    /// Both sides have to agree on it. `Peer::create_offer_with_codec` and
+    /// `Peer::receive_offer_with_codec` pick another one for a single exchange.
+    pub fn sdp_codec(mut self, codec: impl SdpCodec + 'static) -> ConfigurationBuilder {
+        self.config.sdp_codec = Arc::new(codec);
+        self
+    }
+
+    /// Restart ICE with exponential backoff when the connection goes `Disconnected` or `Failed`.
+    /// `signal` delivers the restart offer to the other side and resolves to its answer.
+    /// Only one side of a connection should reconnect.
+    pub fn reconnect<F>(
+        mut self,
+        policy: ReconnectPolicy,
+        signal: impl Fn(String) -> F + Send + Sync + 'static,
+    ) -> ConfigurationBuilder
+    where
+        F: Future<Output = Result<String>> + Send + 'static,
+    {
+        self.config.reconnect = Some(Reconnect {
+            policy,
+            signal: Arc::new(move |offer| Box::pin(signal(offer))),
+        });
+        self
+    }
+
+    /// Negotiate automatically whenever channels are added, also after the connection is up.
+    /// `send_description` delivers offers and answers to the other side, which applies them
+    /// with `Peer::receive_description`. The two sides need different roles.
+    pub fn negotiation<F>(
+        mut self,
+        role: NegotiationRole,
+        send_description: impl Fn(String) -> F + Send + Sync + 'static,
+    ) -> ConfigurationBuilder
+    where
+        F: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.config.negotiation = Some(Negotiation {
+            role,
+            send_description: Arc::new(move |description| Box::pin(send_description(description))),
+        });
+        self
+    }
+
+    /// Emit a `PeerEvent::Stats` snapshot on this interval
+    pub fn stats_interval(mut self, interval: Duration) -> ConfigurationBuilder {
+        self.config.stats_interval = Some(interval);
+        self
+    }
+
+    /// Ping the other side every `interval` on the `HEARTBEAT_CHANNEL` once connected, reporting
+    /// the round trip time as `PeerEvent::Heartbeat`. Without a pong for `timeout` the peer emits
+    /// `PeerEvent::Unresponsive` and is closed, also while a reconnect is under way.
+    /// The other side has to answer the pings, every `Peer` does.
+    pub fn heartbeat(mut self, interval: Duration, timeout: Duration) -> ConfigurationBuilder {
+        self.config.heartbeat = Some(Heartbeat { interval, timeout });
+        self
+    }
+
+    /// Emit `PeerEvent::Idle` and close the peer when no messages were sent or received on its
+    /// channels for `timeout`, heartbeats do not count
+    pub fn idle_timeout(mut self, timeout: Duration) -> ConfigurationBuilder {
+        self.config.idle_timeout = Some(timeout);
+        self
+    }
+
+    pub fn build(self) -> Configuration {
+        self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "at least 1")]
+    fn event_queue_needs_room_for_an_event() {
+        ConfigurationBuilder::new().event_queue(0, BackpressurePolicy::Block);
+    }
+
+    #[test]
+    fn event_queue_takes_capacity_and_policy() {
+        let config = ConfigurationBuilder::new()
+            .event_queue(1, BackpressurePolicy::DropOldest)
+            .build();
+        assert_eq!(config.event_queue_capacity, Some(1));
+        assert_eq!(config.backpressure_policy, BackpressurePolicy::DropOldest);
+    }
+}
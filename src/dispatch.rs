@@ -0,0 +1,307 @@
+use crate::{PeerEvent, PeerEventStream};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+use tokio_stream::StreamExt;
+
+/// How the `handle_message` callback of `Peer::new_with_configuration` is driven
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DispatchMode {
+    /// Await every event one after another
+    #[default]
+    Serial,
+    /// Run the messages of different data channels in parallel, keeping their order within a channel.
+    /// Connection state, channel open/close and other events are still handled one at a time
+    /// and a channel's close event waits for its pending messages. With a bounded event queue
+    /// the messages waiting on all channels together share its capacity, so a slow channel only
+    /// holds back the others once its messages alone fill it.
+    PerChannel,
+}
+
+/// Drive `handle_message` with the events of one peer until its stream ends
+pub(crate) async fn dispatch<T>(
+    mode: DispatchMode,
+    peer_id: u128,
+    events: PeerEventStream,
+    handle_message: impl Fn(u128, PeerEvent) -> T + Send + Sync + 'static,
+    channel_queue_capacity: Option<usize>,
+) where
+    T: Future<Output = ()> + Send + Sync + 'static,
+{
+    match mode {
+        DispatchMode::Serial => dispatch_serial(peer_id, events, handle_message).await,
+        DispatchMode::PerChannel => {
+            dispatch_per_channel(peer_id, events, handle_message, channel_queue_capacity).await
+        }
+    }
+}
+
+async fn dispatch_serial<T>(
+    peer_id: u128,
+    mut events: PeerEventStream,
+    handle_message: impl Fn(u128, PeerEvent) -> T + Send + Sync + 'static,
+) where
+    T: Future<Output = ()> + Send + Sync,
+{
+    while let Some(e) = events.next().await {
+        handle_message(peer_id, e).await;
+    }
+}
+
+struct ChannelWorker {
+    /// Messages with the slot they take up in the shared bound, if there is one
+    messages: mpsc::UnboundedSender<(PeerEvent, Option<OwnedSemaphorePermit>)>,
+    task: JoinHandle<()>,
+}
+
+async fn dispatch_per_channel<T>(
+    peer_id: u128,
+    mut events: PeerEventStream,
+    handle_message: impl Fn(u128, PeerEvent) -> T + Send + Sync + 'static,
+    channel_queue_capacity: Option<usize>,
+) where
+    T: Future<Output = ()> + Send + Sync + 'static,
+{
+    let handle_message = Arc::new(handle_message);
+    let slots = channel_queue_capacity.map(|capacity| Arc::new(Semaphore::new(capacity)));
+    // keyed by the channel's address, the SCTP stream id may not be assigned yet
+    let mut workers: HashMap<usize, ChannelWorker> = HashMap::new();
+
+    while let Some(e) = events.next().await {
+        match e {
+            PeerEvent::DataChannelMessage(c, m) => {
+                // waits while the channels are full, which pushes back on the event queue
+                let slot = match &slots {
+                    Some(slots) => slots.clone().acquire_owned().await.ok(),
+                    None => None,
+                };
+                let key = Arc::as_ptr(&c) as usize;
+                let worker = workers.entry(key).or_insert_with(|| {
+                    let (messages, mut rx) = mpsc::unbounded_channel();
+                    let handle_message = handle_message.clone();
+                    let task = tokio::spawn(async move {
+                        while let Some((e, slot)) = rx.recv().await {
+                            drop(slot);
+                            handle_message(peer_id, e).await;
+                        }
+                    });
+                    ChannelWorker { messages, task }
+                });
+                let _ = worker
+                    .messages
+                    .send((PeerEvent::DataChannelMessage(c, m), slot));
+            }
+            PeerEvent::DataChannelStateChange(c) => {
+                // deliver everything received on the channel before it is reported closed
+                if let Some(worker) = workers.remove(&(Arc::as_ptr(&c) as usize)) {
+                    drop(worker.messages);
+                    let _ = worker.task.await;
+                }
+                handle_message(peer_id, PeerEvent::DataChannelStateChange(c)).await;
+            }
+            e => handle_message(peer_id, e).await,
+        }
+    }
+    for (_, worker) in workers {
+        drop(worker.messages);
+        let _ = worker.task.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::EventQueue;
+    use crate::{BackpressurePolicy, DataChannel, DataChannelMessage};
+    use bytes::Bytes;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tokio::sync::Notify;
+    use webrtc::data_channel::RTCDataChannel;
+
+    /// What a handler saw, messages as channel and number
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Seen {
+        Message(usize, u8),
+        Closed(usize),
+    }
+
+    struct Events {
+        queue: Arc<EventQueue>,
+        channels: Vec<DataChannel>,
+    }
+
+    impl Events {
+        fn new(channels: usize) -> Events {
+            Events {
+                queue: Arc::new(EventQueue::new(None, BackpressurePolicy::Block)),
+                channels: (0..channels)
+                    .map(|_| Arc::new(RTCDataChannel::default()))
+                    .collect(),
+            }
+        }
+
+        fn stream(&self) -> PeerEventStream {
+            PeerEventStream {
+                queue: self.queue.clone(),
+            }
+        }
+
+        async fn message(&self, channel: usize, n: u8) {
+            let message = DataChannelMessage {
+                is_string: false,
+                data: Bytes::from(vec![n]),
+            };
+            let c = self.channels[channel].clone();
+            self.queue
+                .push(PeerEvent::DataChannelMessage(c, message))
+                .await;
+        }
+
+        async fn close(&self, channel: usize) {
+            let c = self.channels[channel].clone();
+            self.queue.push(PeerEvent::DataChannelStateChange(c)).await;
+        }
+
+        fn seen(&self, e: &PeerEvent) -> Seen {
+            let channel = |c: &DataChannel| {
+                self.channels
+                    .iter()
+                    .position(|channel| Arc::ptr_eq(channel, c))
+                    .unwrap()
+            };
+            match e {
+                PeerEvent::DataChannelMessage(c, m) => Seen::Message(channel(c), m.data[0]),
+                PeerEvent::DataChannelStateChange(c) => Seen::Closed(channel(c)),
+                _ => panic!("unexpected event"),
+            }
+        }
+    }
+
+    /// Dispatch everything queued on `events` per channel, `handle` is called with what arrived
+    async fn dispatch_all<T>(
+        events: Arc<Events>,
+        capacity: Option<usize>,
+        handle: impl Fn(Seen) -> T + Send + Sync + 'static,
+    ) where
+        T: Future<Output = ()> + Send + Sync + 'static,
+    {
+        events.queue.finish();
+        let stream = events.stream();
+        let handle_message = move |_, e: PeerEvent| handle(events.seen(&e));
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            dispatch(
+                DispatchMode::PerChannel,
+                0,
+                stream,
+                handle_message,
+                capacity,
+            ),
+        )
+        .await
+        .expect("dispatching got stuck");
+    }
+
+    #[tokio::test]
+    async fn messages_keep_their_order_within_a_channel() {
+        let events = Arc::new(Events::new(2));
+        for n in 0..20 {
+            events.message(0, n).await;
+            events.message(1, n).await;
+        }
+        let seen = Arc::new(Mutex::new(vec![]));
+        let recorded = seen.clone();
+        dispatch_all(events, Some(4), move |e| {
+            let recorded = recorded.clone();
+            async move {
+                // the first channel is slower, so the channels interleave
+                if let Seen::Message(0, _) = e {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+                recorded.lock().unwrap().push(e);
+            }
+        })
+        .await;
+
+        let seen = seen.lock().unwrap();
+        for channel in 0..2 {
+            let numbers: Vec<u8> = seen
+                .iter()
+                .filter_map(|e| match e {
+                    Seen::Message(c, n) if *c == channel => Some(*n),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(numbers, (0..20).collect::<Vec<_>>());
+        }
+    }
+
+    /// The second channel's messages are all handled while the first one's handler is stuck
+    async fn stuck_first_channel(stuck: usize, capacity: Option<usize>) {
+        let events = Arc::new(Events::new(2));
+        for n in 0..stuck {
+            events.message(0, n as u8).await;
+        }
+        for n in 0..10 {
+            events.message(1, n).await;
+        }
+        let release = Arc::new(Semaphore::new(0));
+        let second_done = Arc::new(Notify::new());
+        let (release_clone, second_done_clone) = (release.clone(), second_done.clone());
+        let dispatching = tokio::spawn(dispatch_all(events, capacity, move |e| {
+            let (release, second_done) = (release_clone.clone(), second_done_clone.clone());
+            async move {
+                match e {
+                    Seen::Message(0, _) => release.acquire().await.unwrap().forget(),
+                    Seen::Message(1, 9) => second_done.notify_one(),
+                    _ => {}
+                }
+            }
+        }));
+
+        tokio::time::timeout(Duration::from_secs(5), second_done.notified())
+            .await
+            .expect("the second channel waited for the first");
+        release.add_permits(stuck);
+        dispatching.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_stuck_channel_does_not_hold_back_the_others() {
+        stuck_first_channel(2000, None).await;
+    }
+
+    #[tokio::test]
+    async fn a_stuck_channel_leaves_the_others_their_share_of_the_bound() {
+        stuck_first_channel(3, Some(8)).await;
+    }
+
+    #[tokio::test]
+    async fn close_waits_for_the_channel_and_ends_its_worker() {
+        let events = Arc::new(Events::new(1));
+        for n in 0..5 {
+            events.message(0, n).await;
+        }
+        events.close(0).await;
+        // a channel reopened with the same address gets a new worker
+        events.message(0, 5).await;
+        let seen = Arc::new(Mutex::new(vec![]));
+        let recorded = seen.clone();
+        dispatch_all(events, None, move |e| {
+            let recorded = recorded.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(2)).await;
+                recorded.lock().unwrap().push(e);
+            }
+        })
+        .await;
+
+        let mut expected: Vec<Seen> = (0..5).map(|n| Seen::Message(0, n)).collect();
+        expected.push(Seen::Closed(0));
+        expected.push(Seen::Message(0, 5));
+        assert_eq!(*seen.lock().unwrap(), expected);
+    }
+}
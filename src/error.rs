@@ -0,0 +1,124 @@
+use crate::rpc::RpcError;
+use std::fmt;
+use std::string::FromUtf8Error;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A session description or candidate could not be encoded for signaling
+    SignalingEncode(String),
+    /// A signaling string could not be decoded into a session description or candidate
+    SignalingDecode(String),
+    /// Talking to the signaling server failed or it rejected a message
+    Signaling(String),
+    /// A message could not be encoded for a `TypedChannel`
+    MessageEncode(String),
+    /// A message on a `TypedChannel` did not decode as the expected type
+    MessageDecode(String),
+    /// A message is larger than the limit of a `FragmentedChannel`
+    MessageTooLarge { size: usize, limit: usize },
+    /// A `FragmentedChannel` is already reassembling as many messages as it may
+    TooManyPartialMessages(usize),
+    /// The other side of a `FileTransfer` rejected or aborted it
+    Transfer(String),
+    /// A received file does not match the SHA-256 its sender announced
+    ChecksumMismatch,
+    /// Reading or writing a file failed
+    Io(std::io::Error),
+    /// The other side of an `RpcChannel` answered a call with an error
+    Rpc(RpcError),
+    /// No response arrived in time
+    Timeout,
+    /// The data channel closed before the operation finished
+    ChannelClosed,
+    /// Creating or applying a session description failed
+    Sdp(webrtc::Error),
+    /// Gathering or adding ICE candidates failed
+    Ice(webrtc::Error),
+    /// Creating or using a data channel failed
+    Channel(webrtc::Error),
+    /// The peer has already been closed
+    Closed,
+    /// A shutdown has started, no new peers are accepted
+    ShuttingDown,
+    /// Any other error from the underlying WebRTC stack
+    WebRtc(webrtc::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::SignalingEncode(e) => write!(f, "could not encode signaling message: {}", e),
+            Error::SignalingDecode(e) => write!(f, "could not decode signaling message: {}", e),
+            Error::Signaling(e) => write!(f, "signaling failed: {}", e),
+            Error::MessageEncode(e) => write!(f, "could not encode message: {}", e),
+            Error::MessageDecode(e) => write!(f, "could not decode message: {}", e),
+            Error::MessageTooLarge { size, limit } => {
+                write!(f, "message of {} bytes exceeds the limit of {}", size, limit)
+            }
+            Error::TooManyPartialMessages(limit) => {
+                write!(f, "more than {} messages are partially received", limit)
+            }
+            Error::Transfer(e) => write!(f, "file transfer failed: {}", e),
+            Error::ChecksumMismatch => write!(f, "received file does not match its SHA-256"),
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Rpc(e) => write!(f, "call failed: {}", e),
+            Error::Timeout => write!(f, "timed out"),
+            Error::ChannelClosed => write!(f, "data channel is closed"),
+            Error::Sdp(e) => write!(f, "session description error: {}", e),
+            Error::Ice(e) => write!(f, "ICE error: {}", e),
+            Error::Channel(e) => write!(f, "data channel error: {}", e),
+            Error::Closed => write!(f, "peer is closed"),
+            Error::ShuttingDown => write!(f, "shutting down"),
+            Error::WebRtc(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Sdp(e) | Error::Ice(e) | Error::Channel(e) | Error::WebRtc(e) => Some(e),
+            Error::Rpc(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::SignalingEncode(_)
+            | Error::SignalingDecode(_)
+            | Error::Signaling(_)
+            | Error::MessageEncode(_)
+            | Error::MessageDecode(_)
+            | Error::MessageTooLarge { .. }
+            | Error::TooManyPartialMessages(_)
+            | Error::Transfer(_)
+            | Error::ChecksumMismatch
+            | Error::Timeout
+            | Error::ChannelClosed
+            | Error::Closed
+            | Error::ShuttingDown => None,
+        }
+    }
+}
+
+impl From<webrtc::Error> for Error {
+    fn from(e: webrtc::Error) -> Self {
+        Error::WebRtc(e)
+    }
+}
+
+impl From<base64::DecodeError> for Error {
+    fn from(e: base64::DecodeError) -> Self {
+        Error::SignalingDecode(e.to_string())
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(e: FromUtf8Error) -> Self {
+        Error::SignalingDecode(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::SignalingDecode(e.to_string())
+    }
+}
@@ -0,0 +1,374 @@
+//! Files sent over a dedicated data channel, see `FileTransfer`.
+//!
+//! Control messages are JSON text tagged by `type`, file contents are binary messages:
+//!
+//! ```text
+//! sender   {"type":"offer","name":"map.png","size":1048576,"sha256":"9f86d0…"}
+//! receiver {"type":"accept","offset":0}
+//! sender   <binary chunks from offset to size>
+//! receiver {"type":"verified"}
+//! ```
+//!
+//! Either side may answer with `{"type":"error","message":"…"}` instead, which ends the
+//! transfer. A receiver that already has the start of the file accepts at a later offset, so a
+//! transfer cut off by a reconnect resumes where it stopped. `cyberdeck-client-web-sys` speaks
+//! the same protocol.
+
+use crate::pacing::SendPacer;
+use crate::{DataChannel, Error, Result};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+
+/// Size of the binary messages carrying file contents, small enough for any browser
+pub const FILE_CHUNK_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TransferMessage {
+    Offer {
+        name: String,
+        size: u64,
+        sha256: String,
+    },
+    Accept {
+        offset: u64,
+    },
+    Verified,
+    Error {
+        message: String,
+    },
+}
+
+/// What a sender announces before the contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    /// Whatever the sender calls the file, not a safe path to write to
+    pub name: String,
+    pub size: u64,
+    /// Lowercase hex of the SHA-256 of the whole file
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransferProgress {
+    /// Bytes the receiver has, including those it had before a resumed transfer
+    pub transferred: u64,
+    pub size: u64,
+}
+
+type ProgressFn = Arc<dyn Fn(TransferProgress) + Send + Sync>;
+
+enum Source<'a> {
+    File(File),
+    Bytes(&'a [u8]),
+}
+
+/// One end of a file transfer on a channel of its own, one of the
+/// [channel wrappers](crate#channel-wrappers). Transfers on the same channel run one after another.
+pub struct FileTransfer {
+    channel: DataChannel,
+    messages: mpsc::UnboundedReceiver<DataChannelMessage>,
+    chunk_size: usize,
+    on_progress: Option<ProgressFn>,
+}
+
+impl FileTransfer {
+    pub fn new(channel: DataChannel) -> FileTransfer {
+        let (messages, messages_rx) = mpsc::unbounded_channel();
+        channel.on_message(Box::new(move |msg: DataChannelMessage| {
+            let _ = messages.send(msg);
+            Box::pin(async {})
+        }));
+        FileTransfer {
+            channel,
+            messages: messages_rx,
+            chunk_size: FILE_CHUNK_SIZE,
+            on_progress: None,
+        }
+    }
+
+    /// Size of the chunks this side sends, `FILE_CHUNK_SIZE` by default
+    pub fn chunk_size(mut self, chunk_size: usize) -> FileTransfer {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Called as chunks are sent or received
+    pub fn on_progress(
+        mut self,
+        on_progress: impl Fn(TransferProgress) + Send + Sync + 'static,
+    ) -> FileTransfer {
+        self.on_progress = Some(Arc::new(on_progress));
+        self
+    }
+
+    pub fn channel(&self) -> &DataChannel {
+        &self.channel
+    }
+
+    /// Send the file at `path` under its file name, returns once the receiver verified it
+    pub async fn send_file(&mut self, path: impl AsRef<Path>) -> Result<FileMetadata> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut file = File::open(path).await.map_err(Error::Io)?;
+        let size = file.metadata().await.map_err(Error::Io)?.len();
+        let sha256 = hash_file(&mut file).await?;
+        let metadata = FileMetadata { name, size, sha256 };
+        self.send(metadata, Source::File(file)).await
+    }
+
+    /// Send `data` as a file called `name`, returns once the receiver verified it
+    pub async fn send_bytes(&mut self, name: &str, data: &[u8]) -> Result<FileMetadata> {
+        let metadata = FileMetadata {
+            name: name.to_string(),
+            size: data.len() as u64,
+            sha256: hex(&Sha256::digest(data)),
+        };
+        self.send(metadata, Source::Bytes(data)).await
+    }
+
+    async fn send(
+        &mut self,
+        metadata: FileMetadata,
+        mut source: Source<'_>,
+    ) -> Result<FileMetadata> {
+        self.send_message(&TransferMessage::Offer {
+            name: metadata.name.clone(),
+            size: metadata.size,
+            sha256: metadata.sha256.clone(),
+        })
+        .await?;
+        let offset = match self.next_message().await? {
+            TransferMessage::Accept { offset } if offset <= metadata.size => offset,
+            TransferMessage::Error { message } => return Err(Error::Transfer(message)),
+            other => return Err(self.unexpected(&other).await),
+        };
+
+        let pacer = SendPacer::new(self.channel.clone()).await;
+        if let Source::File(file) = &mut source {
+            file.seek(SeekFrom::Start(offset))
+                .await
+                .map_err(Error::Io)?;
+        }
+        let mut sent = offset;
+        let mut chunk = vec![0; self.chunk_size];
+        self.progress(sent, metadata.size);
+        while sent < metadata.size {
+            let length = ((metadata.size - sent) as usize).min(self.chunk_size);
+            let data = match &mut source {
+                Source::File(file) => {
+                    file.read_exact(&mut chunk[..length])
+                        .await
+                        .map_err(Error::Io)?;
+                    Bytes::copy_from_slice(&chunk[..length])
+                }
+                Source::Bytes(data) => Bytes::copy_from_slice(&data[sent as usize..][..length]),
+            };
+            // the receiver only speaks up early to give up, e.g. when its disk is full
+            if let Ok(msg) = self.messages.try_recv() {
+                return match serde_json::from_slice(&msg.data) {
+                    Ok(TransferMessage::Error { message }) => Err(Error::Transfer(message)),
+                    _ => Err(self.fail("unexpected message while sending").await),
+                };
+            }
+            pacer.wait_for_room().await;
+            self.channel.send(&data).await.map_err(Error::Channel)?;
+            sent += length as u64;
+            self.progress(sent, metadata.size);
+        }
+
+        match self.next_message().await? {
+            TransferMessage::Verified => Ok(metadata),
+            TransferMessage::Error { message } => Err(Error::Transfer(message)),
+            other => Err(self.unexpected(&other).await),
+        }
+    }
+
+    /// Wait for the other side to offer a file
+    pub async fn receive(&mut self) -> Result<IncomingFile<'_>> {
+        match self.next_message().await? {
+            TransferMessage::Offer { name, size, sha256 } => Ok(IncomingFile {
+                transfer: self,
+                metadata: FileMetadata {
+                    name,
+                    size,
+                    sha256: sha256.to_lowercase(),
+                },
+            }),
+            other => Err(self.unexpected(&other).await),
+        }
+    }
+
+    async fn next_message(&mut self) -> Result<TransferMessage> {
+        let msg = self.messages.recv().await.ok_or(Error::ChannelClosed)?;
+        if !msg.is_string {
+            return Err(self.fail("unexpected binary message").await);
+        }
+        match serde_json::from_slice(&msg.data) {
+            Ok(message) => Ok(message),
+            Err(e) => Err(self.fail(&e.to_string()).await),
+        }
+    }
+
+    async fn send_message(&self, message: &TransferMessage) -> Result<()> {
+        let text =
+            serde_json::to_string(message).map_err(|e| Error::MessageEncode(e.to_string()))?;
+        self.channel.send_text(text).await.map_err(Error::Channel)?;
+        Ok(())
+    }
+
+    async fn unexpected(&self, message: &TransferMessage) -> Error {
+        self.fail(&format!("unexpected message {:?}", message))
+            .await
+    }
+
+    /// Tell the other side the transfer is over
+    async fn fail(&self, message: &str) -> Error {
+        let _ = self
+            .send_message(&TransferMessage::Error {
+                message: message.to_string(),
+            })
+            .await;
+        Error::Transfer(message.to_string())
+    }
+
+    fn progress(&self, transferred: u64, size: u64) {
+        if let Some(on_progress) = &self.on_progress {
+            on_progress(TransferProgress { transferred, size });
+        }
+    }
+}
+
+/// A file offered by the other side, returned by `FileTransfer::receive`
+pub struct IncomingFile<'a> {
+    transfer: &'a mut FileTransfer,
+    metadata: FileMetadata,
+}
+
+impl IncomingFile<'_> {
+    pub fn metadata(&self) -> &FileMetadata {
+        &self.metadata
+    }
+
+    /// Receive the file into `path`. It is written to a part file next to it first, named after
+    /// `path` and the announced SHA-256, and a later attempt to save the same file resumes after
+    /// what that part file holds. Once the SHA-256 was verified the part file replaces `path`.
+    /// A part file that does not match is deleted, so that the next attempt starts from scratch,
+    /// and `save` fails with `Error::ChecksumMismatch`.
+    pub async fn save(self, path: impl AsRef<Path>) -> Result<FileMetadata> {
+        let path = path.as_ref();
+        let transfer = self.transfer;
+        let part_path = match part_path(path, &self.metadata.sha256) {
+            Some(part_path) => part_path,
+            None => return Err(transfer.fail("invalid sha256").await),
+        };
+        let size = self.metadata.size;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            // what is already there is the start of the file
+            .truncate(false)
+            .open(&part_path)
+            .await
+            .map_err(Error::Io)?;
+        let mut received = file.metadata().await.map_err(Error::Io)?.len();
+        if received > size {
+            file.set_len(0).await.map_err(Error::Io)?;
+            received = 0;
+        }
+        file.seek(SeekFrom::Start(received))
+            .await
+            .map_err(Error::Io)?;
+
+        transfer
+            .send_message(&TransferMessage::Accept { offset: received })
+            .await?;
+        transfer.progress(received, size);
+        let result = async {
+            while received < size {
+                let msg = transfer.messages.recv().await.ok_or(Error::ChannelClosed)?;
+                if msg.is_string {
+                    return match serde_json::from_slice(&msg.data) {
+                        Ok(TransferMessage::Error { message }) => Err(Error::Transfer(message)),
+                        _ => Err(transfer.fail("expected file contents").await),
+                    };
+                }
+                received += msg.data.len() as u64;
+                if received > size {
+                    return Err(transfer.fail("more data than announced").await);
+                }
+                file.write_all(&msg.data).await.map_err(Error::Io)?;
+                transfer.progress(received, size);
+            }
+            Ok(())
+        }
+        .await;
+        // what arrived is kept for resuming
+        file.flush().await.map_err(Error::Io)?;
+        result?;
+
+        let verified = hash_file(&mut file).await? == self.metadata.sha256;
+        drop(file);
+        if !verified {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            let _ = transfer.fail("checksum mismatch").await;
+            return Err(Error::ChecksumMismatch);
+        }
+        tokio::fs::rename(&part_path, path)
+            .await
+            .map_err(Error::Io)?;
+        transfer.send_message(&TransferMessage::Verified).await?;
+        Ok(self.metadata)
+    }
+
+    /// Turn the file down, the sender fails with `Error::Transfer(reason)`
+    pub async fn reject(self, reason: &str) -> Result<()> {
+        self.transfer
+            .send_message(&TransferMessage::Error {
+                message: reason.to_string(),
+            })
+            .await
+    }
+}
+
+/// `<path>.<start of sha256>.part`, so a different file saved to the same path does not resume
+/// from it. `None` if `sha256` is not what a sender should announce.
+fn part_path(path: &Path, sha256: &str) -> Option<PathBuf> {
+    let is_hex = sha256.len() == 64 && sha256.bytes().all(|b| b.is_ascii_hexdigit());
+    if !is_hex {
+        return None;
+    }
+    let mut part_path = path.as_os_str().to_owned();
+    part_path.push(format!(".{}.part", &sha256[..16]));
+    Some(PathBuf::from(part_path))
+}
+
+async fn hash_file(file: &mut File) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    file.seek(SeekFrom::Start(0)).await.map_err(Error::Io)?;
+    loop {
+        let read = file.read(&mut buffer).await.map_err(Error::Io)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
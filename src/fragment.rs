@@ -0,0 +1,533 @@
+//! Messages of any size on channels limited by the SCTP max message size, see `FragmentedChannel`.
+//!
+//! Every message on a fragmented channel is a frame, starting with its kind:
+//!
+//! ```text
+//! complete:  [0 | text] payload
+//! start:     [1 | text] id: u32 BE, total size: u32 BE, payload
+//! continue:  [2]        id: u32 BE, payload
+//! ```
+//!
+//! `text` is `0x80` for messages sent with `send_text`. Chunks of a message are sent in order,
+//! so the channel has to be ordered and reliable, which is the default.
+
+use crate::{DataChannel, Error, Result};
+use bytes::{BufMut, Bytes, BytesMut};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_stream::Stream;
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+use webrtc::peer_connection::RTCPeerConnection;
+
+/// Largest message webrtc-rs can send as well as receive, larger ones close the channel
+/// on a native receiver
+pub const SCTP_MAX_MESSAGE_SIZE: usize = 65535;
+
+/// What the other side accepts if its description has no `a=max-message-size`, see RFC 8841
+const DEFAULT_REMOTE_MAX_MESSAGE_SIZE: usize = 65536;
+
+const COMPLETE: u8 = 0;
+const START: u8 = 1;
+const CONTINUE: u8 = 2;
+const TEXT: u8 = 0x80;
+const START_HEADER_SIZE: usize = 9;
+const CONTINUE_HEADER_SIZE: usize = 5;
+
+/// Largest message that can be sent to the other side of `peer_connection`
+pub(crate) async fn max_message_size(peer_connection: &RTCPeerConnection) -> usize {
+    let remote = peer_connection
+        .remote_description()
+        .await
+        .and_then(|description| {
+            description.sdp.lines().find_map(|line| {
+                line.trim()
+                    .strip_prefix("a=max-message-size:")?
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+            })
+        })
+        .unwrap_or(DEFAULT_REMOTE_MAX_MESSAGE_SIZE);
+    // zero means the other side has no limit
+    if remote == 0 {
+        SCTP_MAX_MESSAGE_SIZE
+    } else {
+        remote.min(SCTP_MAX_MESSAGE_SIZE)
+    }
+}
+
+/// How much a `FragmentedChannel` buffers for messages it is still reassembling
+#[derive(Debug, Clone, Copy)]
+pub struct FragmentLimits {
+    pub(crate) max_message_size: usize,
+    pub(crate) max_partial_messages: usize,
+    pub(crate) partial_timeout: Duration,
+}
+
+impl FragmentLimits {
+    /// Messages up to 16 MiB and 16 of them arriving at once, each given up on after 30 seconds
+    /// without a chunk
+    pub fn new() -> FragmentLimits {
+        FragmentLimits {
+            max_message_size: 16 * 1024 * 1024,
+            max_partial_messages: 16,
+            partial_timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Largest message to send or reassemble, larger ones are discarded as soon as their
+    /// first chunk arrives
+    pub fn max_message_size(mut self, max_message_size: usize) -> FragmentLimits {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// How many messages may be incomplete at the same time, chunks of further ones are discarded
+    pub fn max_partial_messages(mut self, max_partial_messages: usize) -> FragmentLimits {
+        self.max_partial_messages = max_partial_messages;
+        self
+    }
+
+    /// How long an incomplete message waits for its next chunk, once it waited longer it is
+    /// dropped when another message starts
+    pub fn partial_timeout(mut self, partial_timeout: Duration) -> FragmentLimits {
+        self.partial_timeout = partial_timeout;
+        self
+    }
+}
+
+impl Default for FragmentLimits {
+    fn default() -> Self {
+        FragmentLimits::new()
+    }
+}
+
+/// Something that arrived on a `FragmentedChannel`
+#[derive(Debug)]
+pub enum FragmentEvent {
+    /// A reassembled message, `is_string` as it was sent
+    Message(DataChannelMessage),
+    /// A message was discarded because it broke a limit or its frames were malformed,
+    /// the channel stays usable
+    Discarded(Error),
+}
+
+struct Partial {
+    is_string: bool,
+    total: usize,
+    data: BytesMut,
+    /// When the last chunk arrived
+    updated: Instant,
+}
+
+struct Reassembler {
+    limits: FragmentLimits,
+    partial: HashMap<u32, Partial>,
+}
+
+impl Reassembler {
+    fn new(limits: FragmentLimits) -> Reassembler {
+        Reassembler {
+            limits,
+            partial: HashMap::new(),
+        }
+    }
+
+    fn receive(&mut self, frame: &Bytes) -> Option<Result<DataChannelMessage>> {
+        let kind = *frame.first()?;
+        let is_string = kind & TEXT != 0;
+        match kind & !TEXT {
+            COMPLETE => {
+                let data = frame.slice(1..);
+                if data.len() > self.limits.max_message_size {
+                    return Some(Err(self.too_large(data.len())));
+                }
+                Some(Ok(DataChannelMessage { is_string, data }))
+            }
+            START if frame.len() >= START_HEADER_SIZE => {
+                let id = read_u32(frame, 1);
+                let total = read_u32(frame, 5) as usize;
+                if total > self.limits.max_message_size {
+                    return Some(Err(self.too_large(total)));
+                }
+                if self.partial.remove(&id).is_some() {
+                    // its remaining chunks could belong to either message
+                    return Some(Err(Error::MessageDecode(format!(
+                        "message {} started again before it was complete",
+                        id
+                    ))));
+                }
+                let timeout = self.limits.partial_timeout;
+                self.partial
+                    .retain(|_, partial| partial.updated.elapsed() < timeout);
+                if self.partial.len() >= self.limits.max_partial_messages {
+                    return Some(Err(Error::TooManyPartialMessages(
+                        self.limits.max_partial_messages,
+                    )));
+                }
+                let partial = Partial {
+                    is_string,
+                    total,
+                    // grows with the chunks instead of trusting `total`
+                    data: BytesMut::new(),
+                    updated: Instant::now(),
+                };
+                self.partial.insert(id, partial);
+                self.append(id, &frame[START_HEADER_SIZE..])
+            }
+            // chunks of a discarded message are dropped silently
+            CONTINUE if frame.len() >= CONTINUE_HEADER_SIZE => {
+                let id = read_u32(frame, 1);
+                if self.partial.contains_key(&id) {
+                    self.append(id, &frame[CONTINUE_HEADER_SIZE..])
+                } else {
+                    None
+                }
+            }
+            _ => Some(Err(Error::MessageDecode(format!(
+                "invalid frame of {} bytes starting with {}",
+                frame.len(),
+                kind
+            )))),
+        }
+    }
+
+    fn append(&mut self, id: u32, chunk: &[u8]) -> Option<Result<DataChannelMessage>> {
+        let partial = self.partial.get_mut(&id)?;
+        if partial.data.len() + chunk.len() > partial.total {
+            let total = partial.total;
+            self.partial.remove(&id);
+            return Some(Err(Error::MessageDecode(format!(
+                "message {} is longer than its announced {} bytes",
+                id, total
+            ))));
+        }
+        partial.data.extend_from_slice(chunk);
+        partial.updated = Instant::now();
+        if partial.data.len() < partial.total {
+            return None;
+        }
+        let partial = self.partial.remove(&id)?;
+        Some(Ok(DataChannelMessage {
+            is_string: partial.is_string,
+            data: partial.data.freeze(),
+        }))
+    }
+
+    fn too_large(&self, size: usize) -> Error {
+        Error::MessageTooLarge {
+            size,
+            limit: self.limits.max_message_size,
+        }
+    }
+}
+
+fn read_u32(frame: &[u8], at: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&frame[at..at + 4]);
+    u32::from_be_bytes(bytes)
+}
+
+/// Sends and receives messages larger than a single data channel message by splitting them
+/// into chunks of the negotiated max message size, see `Peer::max_message_size`. Both sides
+/// need a `FragmentedChannel`, received messages are a stream of `FragmentEvent`s.
+/// One of the [channel wrappers](crate#channel-wrappers).
+pub struct FragmentedChannel {
+    sender: FragmentedSender,
+    events: mpsc::UnboundedReceiver<FragmentEvent>,
+}
+
+impl FragmentedChannel {
+    /// `max_message_size` is the largest chunk the other side accepts
+    pub fn new(
+        channel: DataChannel,
+        max_message_size: usize,
+        limits: FragmentLimits,
+    ) -> FragmentedChannel {
+        let (events, events_rx) = mpsc::unbounded_channel();
+        let mut reassembler = Reassembler::new(limits);
+        channel.on_message(Box::new(move |msg: DataChannelMessage| {
+            let event = match reassembler.receive(&msg.data) {
+                Some(Ok(message)) => Some(FragmentEvent::Message(message)),
+                Some(Err(e)) => Some(FragmentEvent::Discarded(e)),
+                None => None,
+            };
+            if let Some(event) = event {
+                let _ = events.send(event);
+            }
+            Box::pin(async {})
+        }));
+
+        FragmentedChannel {
+            sender: FragmentedSender {
+                channel,
+                // room for the largest header, but never less than a byte of payload
+                chunk_size: max_message_size.max(START_HEADER_SIZE + 1),
+                max_message_size: limits.max_message_size,
+                next_id: Arc::new(AtomicU32::new(0)),
+            },
+            events: events_rx,
+        }
+    }
+
+    pub async fn send(&self, data: &Bytes) -> Result<usize> {
+        self.sender.send(data).await
+    }
+
+    pub async fn send_text(&self, text: &str) -> Result<usize> {
+        self.sender.send_text(text).await
+    }
+
+    /// For sending from other tasks while this one reads
+    pub fn sender(&self) -> FragmentedSender {
+        self.sender.clone()
+    }
+
+    pub fn channel(&self) -> &DataChannel {
+        &self.sender.channel
+    }
+}
+
+impl Stream for FragmentedChannel {
+    type Item = FragmentEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+/// Sending half of a `FragmentedChannel`. Messages sent from several clones at once are
+/// interleaved and still reassembled correctly.
+#[derive(Clone)]
+pub struct FragmentedSender {
+    channel: DataChannel,
+    chunk_size: usize,
+    max_message_size: usize,
+    next_id: Arc<AtomicU32>,
+}
+
+impl FragmentedSender {
+    /// Send `data` in as many chunks as needed, returns the payload bytes sent
+    pub async fn send(&self, data: &Bytes) -> Result<usize> {
+        self.send_frames(data, 0).await
+    }
+
+    pub async fn send_text(&self, text: &str) -> Result<usize> {
+        self.send_frames(&Bytes::copy_from_slice(text.as_bytes()), TEXT)
+            .await
+    }
+
+    async fn send_frames(&self, data: &Bytes, text: u8) -> Result<usize> {
+        if data.len() > self.max_message_size || data.len() > u32::MAX as usize {
+            return Err(Error::MessageTooLarge {
+                size: data.len(),
+                limit: self.max_message_size,
+            });
+        }
+        if data.len() < self.chunk_size {
+            let mut frame = BytesMut::with_capacity(1 + data.len());
+            frame.put_u8(COMPLETE | text);
+            frame.extend_from_slice(data);
+            self.send_frame(frame).await?;
+            return Ok(data.len());
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let first = self.chunk_size - START_HEADER_SIZE;
+        let mut frame = BytesMut::with_capacity(self.chunk_size);
+        frame.put_u8(START | text);
+        frame.put_u32(id);
+        frame.put_u32(data.len() as u32);
+        frame.extend_from_slice(&data[..first]);
+        self.send_frame(frame).await?;
+
+        for chunk in data[first..].chunks(self.chunk_size - CONTINUE_HEADER_SIZE) {
+            let mut frame = BytesMut::with_capacity(CONTINUE_HEADER_SIZE + chunk.len());
+            frame.put_u8(CONTINUE);
+            frame.put_u32(id);
+            frame.extend_from_slice(chunk);
+            self.send_frame(frame).await?;
+        }
+        Ok(data.len())
+    }
+
+    async fn send_frame(&self, frame: BytesMut) -> Result<()> {
+        self.channel
+            .send(&frame.freeze())
+            .await
+            .map_err(Error::Channel)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    fn complete(text: u8, payload: &[u8]) -> Bytes {
+        let mut frame = BytesMut::new();
+        frame.put_u8(COMPLETE | text);
+        frame.extend_from_slice(payload);
+        frame.freeze()
+    }
+
+    fn start(id: u32, total: usize, payload: &[u8]) -> Bytes {
+        let mut frame = BytesMut::new();
+        frame.put_u8(START);
+        frame.put_u32(id);
+        frame.put_u32(total as u32);
+        frame.extend_from_slice(payload);
+        frame.freeze()
+    }
+
+    fn chunk(id: u32, payload: &[u8]) -> Bytes {
+        let mut frame = BytesMut::new();
+        frame.put_u8(CONTINUE);
+        frame.put_u32(id);
+        frame.extend_from_slice(payload);
+        frame.freeze()
+    }
+
+    fn message(received: Option<Result<DataChannelMessage>>) -> DataChannelMessage {
+        match received {
+            Some(Ok(message)) => message,
+            Some(Err(e)) => panic!("discarded: {}", e),
+            None => panic!("message is incomplete"),
+        }
+    }
+
+    fn discarded(received: Option<Result<DataChannelMessage>>) -> Error {
+        match received {
+            Some(Err(e)) => e,
+            Some(Ok(_)) => panic!("message was accepted"),
+            None => panic!("nothing was discarded"),
+        }
+    }
+
+    #[test]
+    fn complete_frames_are_messages() {
+        let mut reassembler = Reassembler::new(FragmentLimits::new());
+        let received = message(reassembler.receive(&complete(0, b"binary")));
+        assert!(!received.is_string);
+        assert_eq!(&received.data[..], b"binary");
+        let received = message(reassembler.receive(&complete(TEXT, b"text")));
+        assert!(received.is_string);
+        assert_eq!(&received.data[..], b"text");
+    }
+
+    #[test]
+    fn chunks_are_reassembled() {
+        let mut reassembler = Reassembler::new(FragmentLimits::new());
+        assert!(reassembler.receive(&start(1, 9, b"abc")).is_none());
+        // another message in between
+        assert!(reassembler.receive(&start(2, 2, b"x")).is_none());
+        assert!(reassembler.receive(&chunk(1, b"def")).is_none());
+        assert_eq!(
+            &message(reassembler.receive(&chunk(2, b"y"))).data[..],
+            b"xy"
+        );
+        assert_eq!(
+            &message(reassembler.receive(&chunk(1, b"ghi"))).data[..],
+            b"abcdefghi"
+        );
+        assert!(reassembler.partial.is_empty());
+    }
+
+    #[test]
+    fn oversize_messages_are_discarded() {
+        let limits = FragmentLimits::new().max_message_size(4);
+        let mut reassembler = Reassembler::new(limits);
+        assert!(matches!(
+            discarded(reassembler.receive(&complete(0, b"12345"))),
+            Error::MessageTooLarge { size: 5, limit: 4 }
+        ));
+        assert!(matches!(
+            discarded(reassembler.receive(&start(1, 5, b"12"))),
+            Error::MessageTooLarge { size: 5, limit: 4 }
+        ));
+        // its chunks are dropped without another error
+        assert!(reassembler.receive(&chunk(1, b"345")).is_none());
+    }
+
+    #[test]
+    fn too_many_partial_messages_are_discarded() {
+        let limits = FragmentLimits::new().max_partial_messages(2);
+        let mut reassembler = Reassembler::new(limits);
+        assert!(reassembler.receive(&start(1, 4, b"a")).is_none());
+        assert!(reassembler.receive(&start(2, 4, b"b")).is_none());
+        assert!(matches!(
+            discarded(reassembler.receive(&start(3, 4, b"c"))),
+            Error::TooManyPartialMessages(2)
+        ));
+        assert!(reassembler.receive(&chunk(3, b"ccc")).is_none());
+        assert_eq!(
+            &message(reassembler.receive(&chunk(1, b"aaa"))).data[..],
+            b"aaaa"
+        );
+        assert!(reassembler.receive(&start(3, 4, b"c")).is_none());
+    }
+
+    #[test]
+    fn overlong_chunks_are_discarded() {
+        let mut reassembler = Reassembler::new(FragmentLimits::new());
+        assert!(reassembler.receive(&start(1, 4, b"ab")).is_none());
+        assert!(matches!(
+            discarded(reassembler.receive(&chunk(1, b"cde"))),
+            Error::MessageDecode(_)
+        ));
+        assert!(reassembler.partial.is_empty());
+        assert!(reassembler.receive(&chunk(1, b"cd")).is_none());
+    }
+
+    #[test]
+    fn restarted_message_is_discarded() {
+        let mut reassembler = Reassembler::new(FragmentLimits::new());
+        assert!(reassembler.receive(&start(1, 4, b"ab")).is_none());
+        assert!(matches!(
+            discarded(reassembler.receive(&start(1, 4, b"xy"))),
+            Error::MessageDecode(_)
+        ));
+        assert!(reassembler.receive(&chunk(1, b"cd")).is_none());
+        assert!(reassembler.partial.is_empty());
+    }
+
+    #[test]
+    fn stale_partial_messages_make_room() {
+        let limits = FragmentLimits::new()
+            .max_partial_messages(1)
+            .partial_timeout(Duration::from_millis(20));
+        let mut reassembler = Reassembler::new(limits);
+        assert!(reassembler.receive(&start(1, 4, b"a")).is_none());
+        assert!(matches!(
+            discarded(reassembler.receive(&start(2, 4, b"b"))),
+            Error::TooManyPartialMessages(1)
+        ));
+        sleep(Duration::from_millis(40));
+        assert!(reassembler.receive(&start(2, 2, b"b")).is_none());
+        assert!(reassembler.receive(&chunk(1, b"aaa")).is_none());
+        assert_eq!(
+            &message(reassembler.receive(&chunk(2, b"b"))).data[..],
+            b"bb"
+        );
+    }
+
+    #[test]
+    fn malformed_frames_are_discarded() {
+        let mut reassembler = Reassembler::new(FragmentLimits::new());
+        assert!(reassembler.receive(&Bytes::new()).is_none());
+        assert!(matches!(
+            discarded(reassembler.receive(&Bytes::from_static(&[START, 0, 0]))),
+            Error::MessageDecode(_)
+        ));
+        assert!(matches!(
+            discarded(reassembler.receive(&Bytes::from_static(&[7]))),
+            Error::MessageDecode(_)
+        ));
+    }
+}
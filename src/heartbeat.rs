@@ -0,0 +1,224 @@
+use crate::queue::EventSender;
+use crate::{stats, DataChannel, PeerEvent, RTCDataChannelInit, RTCDataChannelState};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::RTCPeerConnection;
+
+/// Label of the channel carrying heartbeats. Every `Peer` answers `ping:<n>` on it with
+/// `pong:<n>` and keeps it out of its events, browsers do with `answer_heartbeats` in
+/// `cyberdeck-client-web-sys`.
+pub const HEARTBEAT_CHANNEL: &str = "cyberdeck-heartbeat";
+
+/// Pings older than this many intervals can no longer be answered in time
+const MAX_PENDING_PINGS: usize = 64;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Heartbeat {
+    pub(crate) interval: Duration,
+    pub(crate) timeout: Duration,
+}
+
+/// Watches a peer for missing pongs and for idleness, closing it when either limit is hit
+pub(crate) struct Monitor {
+    heartbeat: Option<Heartbeat>,
+    idle_timeout: Option<Duration>,
+    peer_connection: Weak<RTCPeerConnection>,
+    events: EventSender,
+    pings: Mutex<Pings>,
+}
+
+#[derive(Default)]
+struct Pings {
+    next: u64,
+    /// Sequence numbers of unanswered pings and when they were sent
+    pending: VecDeque<(u64, Instant)>,
+    last_pong: Option<Instant>,
+}
+
+impl Monitor {
+    pub(crate) fn new(
+        heartbeat: Option<Heartbeat>,
+        idle_timeout: Option<Duration>,
+        peer_connection: &Arc<RTCPeerConnection>,
+        events: EventSender,
+    ) -> Monitor {
+        Monitor {
+            heartbeat,
+            idle_timeout,
+            peer_connection: Arc::downgrade(peer_connection),
+            events,
+            pings: Mutex::new(Pings::default()),
+        }
+    }
+
+    pub(crate) fn start(self: &Arc<Self>) {
+        if let Some(heartbeat) = self.heartbeat {
+            tokio::spawn(self.clone().send_pings(heartbeat));
+        }
+        if let Some(idle_timeout) = self.idle_timeout {
+            tokio::spawn(self.clone().watch_idle(idle_timeout));
+        }
+    }
+
+    async fn send_pings(self: Arc<Self>, heartbeat: Heartbeat) {
+        let mut channel: Option<DataChannel> = None;
+        loop {
+            tokio::time::sleep(heartbeat.interval).await;
+            let peer_connection = match self.peer_connection.upgrade() {
+                Some(peer_connection) => peer_connection,
+                None => return,
+            };
+            if self.events.is_closed() {
+                return;
+            }
+
+            let open = match &channel {
+                Some(channel) => channel.ready_state() == RTCDataChannelState::Open,
+                // created once connected so it never triggers a negotiation of its own
+                None if peer_connection.connection_state() == RTCPeerConnectionState::Connected => {
+                    match create_channel(&peer_connection, &self).await {
+                        Ok(created) => channel = Some(created),
+                        Err(e) => self.events.send(PeerEvent::Error(e)).await,
+                    }
+                    false
+                }
+                None => false,
+            };
+            if !open {
+                continue;
+            }
+
+            let now = Instant::now();
+            let ping = {
+                let mut pings = self.pings.lock().unwrap();
+                // the timeout counts from the moment the channel opened
+                let last_pong = *pings.last_pong.get_or_insert(now);
+                if now.duration_since(last_pong) > heartbeat.timeout {
+                    None
+                } else {
+                    let ping = pings.next;
+                    pings.next += 1;
+                    pings.pending.push_back((ping, now));
+                    if pings.pending.len() > MAX_PENDING_PINGS {
+                        pings.pending.pop_front();
+                    }
+                    Some(ping)
+                }
+            };
+            match ping {
+                Some(ping) => {
+                    if let Some(channel) = &channel {
+                        let _ = channel.send_text(format!("ping:{}", ping)).await;
+                    }
+                }
+                None => {
+                    self.give_up(&peer_connection, PeerEvent::Unresponsive)
+                        .await;
+                    return;
+                }
+            }
+        }
+    }
+
+    fn pong(&self, ping: u64) -> Option<Duration> {
+        let now = Instant::now();
+        let mut pings = self.pings.lock().unwrap();
+        let position = pings
+            .pending
+            .iter()
+            .position(|(pending, _)| *pending == ping)?;
+        let (_, sent) = pings.pending[position];
+        // earlier pings were lost, later ones may still be answered
+        pings.pending.drain(..=position);
+        pings.last_pong = Some(now);
+        Some(now.duration_since(sent))
+    }
+
+    async fn watch_idle(self: Arc<Self>, idle_timeout: Duration) {
+        let check_interval = (idle_timeout / 4).max(Duration::from_millis(100));
+        let mut last_activity = Instant::now();
+        let mut last_messages = 0;
+        loop {
+            tokio::time::sleep(check_interval).await;
+            let peer_connection = match self.peer_connection.upgrade() {
+                Some(peer_connection) => peer_connection,
+                None => return,
+            };
+            if self.events.is_closed() {
+                return;
+            }
+
+            let messages: usize = stats::collect_stats(&peer_connection)
+                .await
+                .data_channels
+                .iter()
+                .filter(|channel| channel.label != HEARTBEAT_CHANNEL)
+                .map(|channel| channel.messages_sent + channel.messages_received)
+                .sum();
+            if messages != last_messages {
+                last_messages = messages;
+                last_activity = Instant::now();
+            } else if last_activity.elapsed() >= idle_timeout {
+                self.give_up(&peer_connection, PeerEvent::Idle).await;
+                return;
+            }
+        }
+    }
+
+    /// Deliver `event` as the last one and close the connection
+    async fn give_up(&self, peer_connection: &RTCPeerConnection, event: PeerEvent) {
+        self.events.send(event).await;
+        self.events.finish();
+        let _ = peer_connection.close().await;
+    }
+}
+
+async fn create_channel(
+    peer_connection: &RTCPeerConnection,
+    monitor: &Arc<Monitor>,
+) -> crate::Result<DataChannel> {
+    // a late ping is as good as a lost one
+    let config = RTCDataChannelInit {
+        ordered: Some(false),
+        max_retransmits: Some(0),
+        ..Default::default()
+    };
+    let channel = peer_connection
+        .create_data_channel(HEARTBEAT_CHANNEL, Some(config))
+        .await
+        .map_err(crate::Error::Channel)?;
+    answer_pings(&channel, Some(monitor.clone()));
+    Ok(channel)
+}
+
+/// Answer pings on a heartbeat channel and hand pongs to `monitor`, if this side sends pings
+pub(crate) fn answer_pings(d: &DataChannel, monitor: Option<Arc<Monitor>>) {
+    let channel = d.clone();
+    d.on_message(Box::new(move |msg: DataChannelMessage| {
+        let channel = channel.clone();
+        let monitor = monitor.clone();
+        Box::pin(async move {
+            let text = String::from_utf8_lossy(&msg.data);
+            match text.split_once(':') {
+                Some(("ping", ping)) => {
+                    let _ = channel.send_text(format!("pong:{}", ping)).await;
+                }
+                Some(("pong", ping)) => {
+                    let pong = ping.parse().ok().zip(monitor);
+                    if let Some((ping, monitor)) = pong {
+                        if let Some(round_trip_time) = monitor.pong(ping) {
+                            monitor
+                                .events
+                                .send(PeerEvent::Heartbeat(round_trip_time))
+                                .await;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        })
+    }));
+}
@@ -1,42 +1,140 @@
-use anyhow::anyhow;
-use anyhow::Result;
-use base64::engine::general_purpose::STANDARD;
-use base64::Engine;
+//! WebRTC data channels between native peers and browsers, see `Peer`.
+//!
+//! # Channel wrappers
+//!
+//! `TypedChannel`, `RpcChannel`, `FragmentedChannel`, `FileTransfer` and `DataChannelStream`
+//! take over the messages of a data channel. Wrap the channel right when it opens, messages
+//! arriving before are still delivered as `PeerEvent::DataChannelMessage`, later ones only go
+//! to the wrapper. What the wrapper receives ends once the channel is closed.
+
 pub use bytes::Bytes;
 use std::future::Future;
-use std::mem;
-use std::sync::Arc;
-use tokio::sync::mpsc;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use std::time::Duration;
+pub use tokio_stream::{Stream, StreamExt};
 use webrtc::api::interceptor_registry::register_default_interceptors;
 use webrtc::api::media_engine::MediaEngine;
 use webrtc::api::APIBuilder;
+use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
 pub use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
 pub use webrtc::data_channel::data_channel_message::DataChannelMessage;
 pub use webrtc::data_channel::data_channel_state::RTCDataChannelState;
 pub use webrtc::data_channel::RTCDataChannel;
-use webrtc::ice_transport::ice_server::RTCIceServer;
 use webrtc::interceptor::registry::Registry;
-use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::offer_answer_options::RTCOfferOptions;
 pub use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
 use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 use webrtc::peer_connection::RTCPeerConnection;
 
-pub struct Configuration {
-    stun_or_turn_urls: Vec<String>,
-}
+mod codec;
+mod configuration;
+mod dispatch;
+mod error;
+mod file_transfer;
+mod fragment;
+mod heartbeat;
+mod manager;
+mod negotiation;
+mod pacing;
+mod queue;
+mod reconnect;
+mod rooms;
+pub mod rpc;
+mod shutdown;
+pub mod signaling;
+mod stats;
+mod stream;
+mod token;
+pub mod typed;
+
+pub use codec::{Base64Codec, DeflateCodec, JsonCodec, SdpCodec, UrlSafeBase64Codec};
+pub use configuration::*;
+pub use dispatch::DispatchMode;
+pub use error::{Error, Result};
+pub use file_transfer::{
+    FileMetadata, FileTransfer, IncomingFile, TransferProgress, FILE_CHUNK_SIZE,
+};
+pub use fragment::{
+    FragmentEvent, FragmentLimits, FragmentedChannel, FragmentedSender, SCTP_MAX_MESSAGE_SIZE,
+};
+pub use heartbeat::HEARTBEAT_CHANNEL;
+use heartbeat::Monitor;
+pub use manager::{PeerManager, PeerManagerEvents};
+pub use negotiation::NegotiationRole;
+use negotiation::{Negotiation, Negotiator};
+pub use queue::BackpressurePolicy;
+pub use reconnect::ReconnectPolicy;
+pub use rooms::{RoomEvent, RoomEvents, Rooms};
+pub use rpc::{RpcChannel, RpcError, RpcRouter};
+pub use shutdown::{ShutdownOptions, ShutdownReport};
+pub use stats::{
+    CandidatePairState, CandidatePairStats, CandidateStats, CandidateType, DataChannelStats,
+    PeerStats, RTCSctpTransportState,
+};
+use reconnect::Reconnector;
+use signaling::{OutgoingSignals, Signaler};
+pub use stream::DataChannelStream;
+pub use token::TokenCodec;
+pub use typed::{MessageCodec, TypedChannel, TypedEvent, TypedSender};
+use queue::{EventQueue, EventSender};
 
 pub type DataChannel = Arc<RTCDataChannel>;
 
+/// Every data channel of a peer, local and remote
+type Channels = Arc<Mutex<Vec<Weak<RTCDataChannel>>>>;
+
 pub struct Peer {
     pub peer_id: u128,
     pub peer_connection: Arc<RTCPeerConnection>,
-    abort: mpsc::UnboundedSender<()>,
+    events: EventSender,
+    queue: Arc<EventQueue>,
+    trickle_ice: bool,
+    sdp_codec: Arc<dyn SdpCodec>,
+    negotiator: Option<Arc<Negotiator>>,
+    channels: Channels,
+    closed: bool,
 }
 
 pub enum PeerEvent {
     PeerConnectionStateChange(RTCPeerConnectionState),
     DataChannelStateChange(DataChannel),
     DataChannelMessage(DataChannel, DataChannelMessage),
+    /// A local ICE candidate encoded for signaling, only emitted in trickle ICE mode
+    IceCandidate(String),
+    /// Something went wrong in the background, e.g. while gathering candidates
+    Error(Error),
+    /// Periodic snapshot enabled with `ConfigurationBuilder::stats_interval`
+    Stats(PeerStats),
+    /// Round trip time of a heartbeat, enabled with `ConfigurationBuilder::heartbeat`
+    Heartbeat(Duration),
+    /// No heartbeat was answered within the timeout, the peer is closed and this is its last event
+    Unresponsive,
+    /// Nothing was sent or received within `ConfigurationBuilder::idle_timeout`, the peer is
+    /// closed and this is its last event
+    Idle,
+}
+
+/// Events of a single `Peer`, returned by `Peer::new_with_stream`.
+/// The stream ends once the peer is closed, dropped or its connection failed.
+pub struct PeerEventStream {
+    queue: Arc<EventQueue>,
+}
+
+impl Stream for PeerEventStream {
+    type Item = PeerEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<PeerEvent>> {
+        self.queue.poll_pop(cx)
+    }
+}
+
+impl Drop for PeerEventStream {
+    fn drop(&mut self) {
+        // nobody is listening anymore, release senders blocked on a full queue
+        self.queue.close();
+    }
 }
 
 impl Peer {
@@ -45,24 +143,41 @@ impl Peer {
         stun_or_turn_urls: Option<Vec<String>>
     ) -> Result<Peer>
     where
-        T: Future<Output = ()> + Send + Sync,
+        T: Future<Output = ()> + Send + Sync + 'static,
     {
-        Peer::new_with_configuration(
-            handle_message,
-            Configuration {
-                stun_or_turn_urls: stun_or_turn_urls.unwrap_or(vec!["stun:stun.l.google.com:19302".to_owned()]),
-            },
-        )
-        .await
+        let config = ConfigurationBuilder::new()
+            .ice_server(RTCIceServer {
+                urls: stun_or_turn_urls.unwrap_or(vec![DEFAULT_STUN_URL.to_owned()]),
+                ..Default::default()
+            })
+            .build();
+        Peer::new_with_configuration(handle_message, config).await
     }
 
     pub async fn new_with_configuration<T>(
         handle_message: impl Fn(u128, PeerEvent) -> T + Send + Sync + 'static,
-        mut config: Configuration,
+        config: Configuration,
     ) -> Result<Peer>
     where
-        T: Future<Output = ()> + Send + Sync,
+        T: Future<Output = ()> + Send + Sync + 'static,
     {
+        let dispatch_mode = config.dispatch_mode;
+        let channel_queue_capacity = config.event_queue_capacity;
+        let (c, events) = Peer::new_with_stream(config).await?;
+
+        tokio::spawn(dispatch::dispatch(
+            dispatch_mode,
+            c.peer_id,
+            events,
+            handle_message,
+            channel_queue_capacity,
+        ));
+
+        Ok(c)
+    }
+
+    /// Create a peer whose events are read from the returned stream instead of a callback
+    pub async fn new_with_stream(config: Configuration) -> Result<(Peer, PeerEventStream)> {
         let mut m = MediaEngine::default();
         m.register_default_codecs()?;
         let mut registry = Registry::new();
@@ -73,178 +188,391 @@ impl Peer {
             .with_interceptor_registry(registry)
             .build();
 
-        let config = RTCConfiguration {
-            ice_servers: vec![RTCIceServer {
-                urls: mem::take(&mut config.stun_or_turn_urls),
-                ..Default::default()
-            }],
-            ..Default::default()
-        };
+        let peer_connection = Arc::new(
+            api.new_peer_connection(config.to_rtc_configuration())
+                .await?,
+        );
 
-        let peer_connection = Arc::new(api.new_peer_connection(config).await?);
-
-        let (tx, mut msg_rx) = mpsc::unbounded_channel::<(u128, PeerEvent)>();
-        let tx_clone = tx.clone();
-        let (abort_tx, mut abort_rx) = mpsc::unbounded_channel::<()>();
-        let abort_tx_clone = abort_tx.clone();
+        let queue = Arc::new(EventQueue::new(
+            config.event_queue_capacity,
+            config.backpressure_policy,
+        ));
+        let events = EventSender::new(queue.clone(), &peer_connection);
+        let events_state = events.clone();
 
         let peer_id = Peer::random_peer_id();
         let c = Peer {
             peer_id,
-            peer_connection,
-            abort: abort_tx,
+            peer_connection: peer_connection.clone(),
+            events: events.clone(),
+            queue: queue.clone(),
+            trickle_ice: config.trickle_ice,
+            sdp_codec: config.sdp_codec.clone(),
+            negotiator: config.negotiation.clone().map(|negotiation| {
+                Arc::new(Negotiator::new(
+                    negotiation,
+                    &peer_connection,
+                    config.trickle_ice,
+                    config.sdp_codec.clone(),
+                    events.clone(),
+                ))
+            }),
+            channels: Channels::default(),
+            closed: false,
         };
 
-        tokio::spawn(async move {
-            loop {
-                tokio::select! {
-                    val = msg_rx.recv() => {
-                        if let Some(v) = val {
-                            handle_message(v.0,v.1).await;
-                        }
-                    }
-                    _ = abort_rx.recv() => {
-                        break;
-                    }
-                };
-            }
+        let reconnector = config.reconnect.clone().map(|reconnect| {
+            Arc::new(Reconnector::new(
+                reconnect,
+                &c.peer_connection,
+                c.trickle_ice,
+                c.sdp_codec.clone(),
+                events.clone(),
+            ))
         });
-
+        let closed_channels = c.channels.clone();
         c.peer_connection.on_peer_connection_state_change(Box::new(
             move |s: RTCPeerConnectionState| {
-                match tx_clone.send((peer_id, PeerEvent::PeerConnectionStateChange(s))) {
-                    Ok(_) => (),
-                    Err(error) => {
-                        panic!("Error sending mpsc message: {:?}", error.to_string())
-                    }
-                };
-                if s == RTCPeerConnectionState::Failed {
-                    match abort_tx_clone.send(()) {
-                        Ok(_) => (),
-                        Err(error) => {
-                            panic!("Error sending mpsc message: {:?}", error.to_string())
-                        }
-                    };
+                let events = events_state.clone();
+                let reconnector = reconnector.clone();
+                if s == RTCPeerConnectionState::Closed {
+                    // channels of a connection closed on this side never see their close event
+                    release_message_handlers(&closed_channels);
                 }
-                Box::pin(async {})
+                Box::pin(async move {
+                    events.send(PeerEvent::PeerConnectionStateChange(s)).await;
+                    match (s, reconnector) {
+                        (RTCPeerConnectionState::Disconnected, Some(reconnector))
+                        | (RTCPeerConnectionState::Failed, Some(reconnector)) => {
+                            reconnector.start();
+                        }
+                        (RTCPeerConnectionState::Failed, None) => events.close(),
+                        _ => {}
+                    }
+                })
             },
         ));
 
+        if c.trickle_ice {
+            let events_candidate = events.clone();
+            let sdp_codec = config.sdp_codec.clone();
+            c.peer_connection
+                .on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+                    let events = events_candidate.clone();
+                    let sdp_codec = sdp_codec.clone();
+                    Box::pin(async move {
+                        // a None candidate marks the end of gathering, nothing to forward
+                        if let Some(candidate) = candidate {
+                            let event = match encode_candidate(sdp_codec.as_ref(), &candidate) {
+                                Ok(candidate) => PeerEvent::IceCandidate(candidate),
+                                Err(error) => PeerEvent::Error(error),
+                            };
+                            events.send(event).await;
+                        }
+                    })
+                }));
+        }
+
+        if let Some(negotiator) = &c.negotiator {
+            wire_negotiator(&c.peer_connection, negotiator);
+        }
+
+        if let Some(interval) = config.stats_interval {
+            tokio::spawn(stats::report_stats(
+                Arc::downgrade(&c.peer_connection),
+                events.clone(),
+                interval,
+            ));
+        }
+
+        let channels = c.channels.clone();
+        let monitor = if config.heartbeat.is_some() || config.idle_timeout.is_some() {
+            let monitor = Arc::new(Monitor::new(
+                config.heartbeat,
+                config.idle_timeout,
+                &c.peer_connection,
+                events.clone(),
+            ));
+            monitor.start();
+            Some(monitor)
+        } else {
+            None
+        };
+
         c.peer_connection
             .on_data_channel(Box::new(move |d: Arc<RTCDataChannel>| {
-                let tx1 = tx.clone();
-                let tx2 = tx.clone();
-                let tx3 = tx.clone();
-
-                Box::pin(async move {
-                    let data_cannel_clone1 = d.clone();
-                    let data_cannel_clone2 = d.clone();
-                    let data_cannel_clone3 = d.clone();
-                    d.on_open(Box::new(move || {
-                        match tx1.send((
-                            peer_id,
-                            PeerEvent::DataChannelStateChange(data_cannel_clone1.clone()),
-                        )) {
-                            Ok(_) => (),
-                            Err(error) => {
-                                panic!("Error sending mpsc message: {:?}", error.to_string())
-                            }
-                        };
-                        Box::pin(async {})
-                    }));
-
-                    d.on_close(Box::new(move || {
-                        match tx2.send((
-                            peer_id,
-                            PeerEvent::DataChannelStateChange(data_cannel_clone2.clone()),
-                        )) {
-                            Ok(_) => (),
-                            Err(error) => {
-                                panic!("Error sending mpsc message: {:?}", error.to_string())
-                            }
-                        };
-                        Box::pin(async {})
-                    }));
-
-                    d.on_message(Box::new(move |msg: DataChannelMessage| {
-                        match tx3.send((
-                            peer_id,
-                            PeerEvent::DataChannelMessage(data_cannel_clone3.clone(), msg),
-                        )) {
-                            Ok(_) => (),
-                            Err(error) => {
-                                panic!("Error sending mpsc message: {:?}", error.to_string())
-                            }
-                        };
-                        Box::pin(async {})
-                    }));
-                })
+                if d.label() == HEARTBEAT_CHANNEL {
+                    heartbeat::answer_pings(&d, monitor.clone());
+                    return Box::pin(async {});
+                }
+                wire_data_channel(&d, &events, &channels);
+                Box::pin(async {})
             }));
 
-        Ok(c)
+        Ok((c, PeerEventStream { queue }))
     }
 
     pub async fn create_offer(&mut self) -> Result<String> {
-        let offer = self.peer_connection.create_offer(None).await?;
+        let sdp_codec = self.sdp_codec.clone();
+        self.create_offer_with_codec(sdp_codec.as_ref()).await
+    }
 
-        // Sets the LocalDescription, and starts our UDP listeners
-        // Note: this will start the gathering of ICE candidates
-        let mut gather_complete = self.peer_connection.gathering_complete_promise().await;
-        self.peer_connection.set_local_description(offer).await?;
-        let _ = gather_complete.recv().await;
+    /// `create_offer` in another wire format than the configured `ConfigurationBuilder::sdp_codec`.
+    /// Trickled candidates keep using the configured one.
+    pub async fn create_offer_with_codec(&mut self, sdp_codec: &dyn SdpCodec) -> Result<String> {
+        self.ensure_open()?;
+        let offer = self
+            .peer_connection
+            .create_offer(None)
+            .await
+            .map_err(Error::Sdp)?;
+        local_description(&self.peer_connection, offer, self.trickle_ice, sdp_codec).await
+    }
 
-        if let Some(local_desc) = self.peer_connection.local_description().await {
-            let json_str = serde_json::to_string(&local_desc)?;
-            let b64 = encode(&json_str);
-            Ok(b64)
-        } else {
-            Err(anyhow!("generate local_description failed!"))
-        }
+    /// Create an offer with new ICE credentials to recover a `Disconnected` or `Failed`
+    /// connection, the answer goes to `receive_answer`. Data channels stay open.
+    pub async fn restart_ice(&mut self) -> Result<String> {
+        self.ensure_open()?;
+        ice_restart_offer(&self.peer_connection, self.trickle_ice, self.sdp_codec.as_ref()).await
     }
 
     pub async fn receive_offer(&mut self, offer: &str) -> Result<String> {
-        let desc_data = decode(offer)?.to_string();
+        let sdp_codec = self.sdp_codec.clone();
+        self.receive_offer_with_codec(offer, sdp_codec.as_ref()).await
+    }
+
+    /// `receive_offer` for an offer in another wire format than the configured one, the answer
+    /// is encoded the same way
+    pub async fn receive_offer_with_codec(
+        &mut self,
+        offer: &str,
+        sdp_codec: &dyn SdpCodec,
+    ) -> Result<String> {
+        self.ensure_open()?;
+        let desc_data = sdp_codec.decode(offer)?;
         let offer = serde_json::from_str::<RTCSessionDescription>(&desc_data)?;
-        self.peer_connection.set_remote_description(offer).await?;
-        let answer = self.peer_connection.create_answer(None).await?;
-        let mut gather_complete = self.peer_connection.gathering_complete_promise().await;
-        self.peer_connection.set_local_description(answer).await?;
-        let _ = gather_complete.recv().await;
+        self.peer_connection
+            .set_remote_description(offer)
+            .await
+            .map_err(Error::Sdp)?;
+        let answer = self
+            .peer_connection
+            .create_answer(None)
+            .await
+            .map_err(Error::Sdp)?;
+        local_description(&self.peer_connection, answer, self.trickle_ice, sdp_codec).await
+    }
 
-        if let Some(local_desc) = self.peer_connection.local_description().await {
-            let json_str = serde_json::to_string(&local_desc)?;
-            let b64 = encode(&json_str);
-            Ok(b64)
-        } else {
-            Err(anyhow!("generate local_description failed!"))
+    /// Apply the answer to an offer made with `create_offer`
+    pub async fn receive_answer(&mut self, answer: &str) -> Result<()> {
+        let sdp_codec = self.sdp_codec.clone();
+        self.receive_answer_with_codec(answer, sdp_codec.as_ref()).await
+    }
+
+    /// Apply the answer to an offer made with `create_offer_with_codec`
+    pub async fn receive_answer_with_codec(
+        &mut self,
+        answer: &str,
+        sdp_codec: &dyn SdpCodec,
+    ) -> Result<()> {
+        self.ensure_open()?;
+        apply_answer(&self.peer_connection, answer, sdp_codec).await
+    }
+
+    /// Apply an offer or answer sent by the other side's negotiation callback, offers are
+    /// answered through this side's callback. Requires `ConfigurationBuilder::negotiation`.
+    /// The callback runs in the background, its errors are delivered as `PeerEvent::Error`.
+    pub async fn receive_description(&mut self, description: &str) -> Result<()> {
+        self.ensure_open()?;
+        match &self.negotiator {
+            Some(negotiator) => {
+                negotiator
+                    .receive_description(&self.peer_connection, description)
+                    .await
+            }
+            None => Err(Error::Sdp(webrtc::Error::new(
+                "negotiation is not configured".to_owned(),
+            ))),
+        }
+    }
+
+    /// Add a remote ICE candidate received from the other side's `PeerEvent::IceCandidate`
+    pub async fn add_ice_candidate(&mut self, candidate: &str) -> Result<()> {
+        self.ensure_open()?;
+        let candidate_data = self.sdp_codec.decode(candidate)?;
+        let candidate = serde_json::from_str::<RTCIceCandidateInit>(&candidate_data)?;
+        match self.peer_connection.add_ice_candidate(candidate).await {
+            Ok(()) => Ok(()),
+            Err(_) if self.negotiator.as_ref().is_some_and(|n| n.ignoring_offer()) => Ok(()),
+            Err(e) => Err(Error::Ice(e)),
         }
     }
 
-    pub async fn create_channel(&mut self, name: &str) -> Result<(), webrtc::Error> {
-        match self.peer_connection.create_data_channel(name, None).await {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
+    /// Let `signaler` carry offers, answers and candidates from now on. The `Impolite` side sends
+    /// the first offer and channels created later on either side are negotiated automatically.
+    /// Takes over from `ConfigurationBuilder::negotiation` and, in trickle ICE mode, from
+    /// `PeerEvent::IceCandidate`. Returns right away, the connection comes up in the background.
+    pub async fn connect_with(
+        &mut self,
+        signaler: impl Signaler + 'static,
+        role: NegotiationRole,
+    ) -> Result<()> {
+        self.ensure_open()?;
+        let (outgoing, outgoing_rx) = OutgoingSignals::new();
+
+        let descriptions = outgoing.clone();
+        let sdp_codec = self.sdp_codec.clone();
+        let negotiation = Negotiation {
+            role,
+            send_description: Arc::new(move |description| {
+                let queued = descriptions.queue_description(sdp_codec.as_ref(), description);
+                Box::pin(async move { queued })
+            }),
+        };
+        let negotiator = Arc::new(Negotiator::new(
+            negotiation,
+            &self.peer_connection,
+            self.trickle_ice,
+            self.sdp_codec.clone(),
+            self.events.clone(),
+        ));
+        wire_negotiator(&self.peer_connection, &negotiator);
+
+        if self.trickle_ice {
+            let events = self.events.clone();
+            let sdp_codec = self.sdp_codec.clone();
+            self.peer_connection
+                .on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+                    let events = events.clone();
+                    let queued = candidate.map(|candidate| {
+                        encode_candidate(sdp_codec.as_ref(), &candidate)
+                            .and_then(|candidate| outgoing.queue_candidate(candidate))
+                    });
+                    Box::pin(async move {
+                        if let Some(Err(error)) = queued {
+                            events.send(PeerEvent::Error(error)).await;
+                        }
+                    })
+                }));
+        }
+
+        tokio::spawn(signaling::run_signaler(
+            signaler,
+            outgoing_rx,
+            Arc::downgrade(&self.peer_connection),
+            Arc::downgrade(&negotiator),
+            self.sdp_codec.clone(),
+            self.events.clone(),
+        ));
+        if role == NegotiationRole::Impolite {
+            let negotiator = negotiator.clone();
+            tokio::spawn(async move { negotiator.negotiation_needed().await });
         }
+        self.negotiator = Some(negotiator);
+        Ok(())
+    }
+
+    /// Create a data channel from this side, its events are delivered like those of remote channels
+    pub async fn create_channel(&mut self, name: &str) -> Result<DataChannel> {
+        self.ensure_open()?;
+        let channel = self
+            .peer_connection
+            .create_data_channel(name, None)
+            .await
+            .map_err(Error::Channel)?;
+        wire_data_channel(&channel, &self.events, &self.channels);
+        Ok(channel)
     }
 
     pub async fn create_channel_with_configuration(
         &mut self,
         name: &str,
         config: RTCDataChannelInit,
-    ) -> Result<(), webrtc::Error> {
-        match self
+    ) -> Result<DataChannel> {
+        self.ensure_open()?;
+        let channel = self
             .peer_connection
             .create_data_channel(name, Some(config))
             .await
-        {
-            Ok(_) => Ok(()),
-            Err(e) => Err(e),
+            .map_err(Error::Channel)?;
+        wire_data_channel(&channel, &self.events, &self.channels);
+        Ok(channel)
+    }
+
+    /// Close the connection and end the event stream, closing twice is a no-op
+    pub async fn close(&mut self) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        self.events.close();
+        self.peer_connection.close().await?;
+        Ok(())
+    }
+
+    /// Send the goodbye message of `options` on every open channel, wait for buffered data to be
+    /// sent until the deadline and close. Returns false if data was still buffered at the deadline.
+    pub async fn close_gracefully(&mut self, options: &ShutdownOptions) -> Result<bool> {
+        if self.closed {
+            return Ok(true);
+        }
+        let channels = self.channels();
+        let goodbye = options.goodbye.clone();
+        let flushed = tokio::time::timeout(options.deadline, async {
+            if let Some(goodbye) = goodbye {
+                for channel in &channels {
+                    let _ = channel.send_text(goodbye.clone()).await;
+                }
+            }
+            shutdown::flush(&channels).await;
+        })
+        .await
+        .is_ok();
+        self.close().await?;
+        Ok(flushed)
+    }
+
+    /// Data channels that are open right now
+    pub fn channels(&self) -> Vec<DataChannel> {
+        let channels = self.channels.lock().unwrap();
+        channels
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|channel| channel.ready_state() == RTCDataChannelState::Open)
+            .collect()
+    }
+
+    /// Number of events discarded because the event queue was full
+    pub fn dropped_events(&self) -> u64 {
+        self.queue.dropped()
+    }
+
+    /// Queue for what a channel wrapper receives, bounded like the peer's own events
+    pub(crate) fn wrapper_events<T>(&self) -> EventSender<T> {
+        EventSender::new(Arc::new(self.queue.with_same_bound()), &self.peer_connection)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn ensure_open(&self) -> Result<()> {
+        if self.closed {
+            Err(Error::Closed)
+        } else {
+            Ok(())
         }
     }
 
-    pub async fn close(&mut self) -> Result<(), webrtc::Error> {
-        self.abort.send(())?;
-        self.peer_connection.close().await
+    /// Selected candidate pair, round trip time and per channel traffic
+    pub async fn stats(&self) -> PeerStats {
+        stats::collect_stats(&self.peer_connection).await
+    }
+
+    /// Largest message the other side accepts, from the `a=max-message-size` of its description
+    /// and at most `SCTP_MAX_MESSAGE_SIZE`. Larger ones need a `FragmentedChannel`.
+    pub async fn max_message_size(&self) -> usize {
+        fragment::max_message_size(&self.peer_connection).await
     }
 
     pub fn connection_state(&self) -> RTCPeerConnectionState {
@@ -258,16 +586,139 @@ impl Peer {
 
 impl Drop for Peer {
     fn drop(&mut self) {
-        self.abort.send(()).expect("could not stop task on drop");
+        self.events.close();
+    }
+}
+
+/// Set a local description and encode it for signaling, waiting for ICE gathering unless trickling
+async fn local_description(
+    peer_connection: &RTCPeerConnection,
+    desc: RTCSessionDescription,
+    trickle_ice: bool,
+    sdp_codec: &dyn SdpCodec,
+) -> Result<String> {
+    if trickle_ice {
+        // candidates are sent separately as they are gathered
+        peer_connection
+            .set_local_description(desc)
+            .await
+            .map_err(Error::Sdp)?;
+    } else {
+        // Sets the LocalDescription, and starts our UDP listeners
+        // Note: this will start the gathering of ICE candidates
+        let mut gather_complete = peer_connection.gathering_complete_promise().await;
+        peer_connection
+            .set_local_description(desc)
+            .await
+            .map_err(Error::Sdp)?;
+        let _ = gather_complete.recv().await;
+    }
+
+    if let Some(local_desc) = peer_connection.local_description().await {
+        let json_str = serde_json::to_string(&local_desc)
+            .map_err(|e| Error::Sdp(webrtc::Error::new(e.to_string())))?;
+        sdp_codec.encode(&json_str)
+    } else {
+        Err(Error::Sdp(webrtc::Error::new(
+            "generate local_description failed!".to_owned(),
+        )))
+    }
+}
+
+async fn ice_restart_offer(
+    peer_connection: &RTCPeerConnection,
+    trickle_ice: bool,
+    sdp_codec: &dyn SdpCodec,
+) -> Result<String> {
+    let offer = peer_connection
+        .create_offer(Some(RTCOfferOptions {
+            ice_restart: true,
+            ..Default::default()
+        }))
+        .await
+        .map_err(Error::Sdp)?;
+    local_description(peer_connection, offer, trickle_ice, sdp_codec).await
+}
+
+async fn apply_answer(
+    peer_connection: &RTCPeerConnection,
+    answer: &str,
+    sdp_codec: &dyn SdpCodec,
+) -> Result<()> {
+    let desc_data = sdp_codec.decode(answer)?;
+    let answer = serde_json::from_str::<RTCSessionDescription>(&desc_data)?;
+    peer_connection
+        .set_remote_description(answer)
+        .await
+        .map_err(Error::Sdp)
+}
+
+/// Offer whenever channels are added
+fn wire_negotiator(peer_connection: &RTCPeerConnection, negotiator: &Arc<Negotiator>) {
+    let negotiator = negotiator.clone();
+    peer_connection.on_negotiation_needed(Box::new(move || {
+        let negotiator = negotiator.clone();
+        // runs outside the hook, offering waits on the connection's own operations
+        tokio::spawn(async move { negotiator.negotiation_needed().await });
+        Box::pin(async {})
+    }));
+}
+
+/// Nothing arrives on `d` anymore, dropping its handler also ends the streams of wrappers such
+/// as `TypedChannel` or `FileTransfer`
+fn release_message_handler(d: &RTCDataChannel) {
+    d.on_message(Box::new(|_: DataChannelMessage| Box::pin(async {})));
+}
+
+fn release_message_handlers(channels: &Channels) {
+    let channels = channels.lock().unwrap();
+    for channel in channels.iter().filter_map(Weak::upgrade) {
+        release_message_handler(&channel);
     }
 }
 
-fn encode(b: &str) -> String {
-    STANDARD.encode(b)
+/// Forward open/close/message events of a data channel to the peer's event queue
+fn wire_data_channel(d: &DataChannel, events: &EventSender, channels: &Channels) {
+    {
+        let mut channels = channels.lock().unwrap();
+        channels.retain(|channel| {
+            channel
+                .upgrade()
+                .is_some_and(|channel| channel.ready_state() != RTCDataChannelState::Closed)
+        });
+        channels.push(Arc::downgrade(d));
+    }
+
+    let events1 = events.clone();
+    let events2 = events.clone();
+    let events3 = events.clone();
+    let data_cannel_clone1 = d.clone();
+    let data_cannel_clone2 = d.clone();
+    let data_cannel_clone3 = d.clone();
+
+    d.on_open(Box::new(move || {
+        let events = events1.clone();
+        let d = data_cannel_clone1.clone();
+        Box::pin(async move { events.send(PeerEvent::DataChannelStateChange(d)).await })
+    }));
+
+    d.on_close(Box::new(move || {
+        let events = events2.clone();
+        let d = data_cannel_clone2.clone();
+        release_message_handler(&d);
+        Box::pin(async move { events.send(PeerEvent::DataChannelStateChange(d)).await })
+    }));
+
+    d.on_message(Box::new(move |msg: DataChannelMessage| {
+        let events = events3.clone();
+        let d = data_cannel_clone3.clone();
+        Box::pin(async move { events.send(PeerEvent::DataChannelMessage(d, msg)).await })
+    }));
 }
 
-fn decode(s: &str) -> Result<String> {
-    let b = STANDARD.decode(s)?;
-    let s = String::from_utf8(b)?;
-    Ok(s)
+fn encode_candidate(sdp_codec: &dyn SdpCodec, candidate: &RTCIceCandidate) -> Result<String> {
+    let candidate = candidate.to_json().map_err(Error::Ice)?;
+    let json_str = serde_json::to_string(&candidate)
+        .map_err(|e| Error::Ice(webrtc::Error::new(e.to_string())))?;
+    sdp_codec.encode(&json_str)
 }
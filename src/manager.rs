@@ -0,0 +1,256 @@
+use crate::shutdown::{self, ShutdownOptions, ShutdownReport};
+use crate::{DataChannel, Error, Peer, PeerEvent, PeerEventStream, RTCDataChannelState, Result};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio_stream::{Stream, StreamExt};
+
+/// Capacity of the merged event stream, peers wait for the reader once it is full
+const MERGED_EVENT_CAPACITY: usize = 1024;
+
+/// Keeps many peers alive and reachable by id until their connection is closed or fails.
+/// Clones share the same peers.
+#[derive(Clone)]
+pub struct PeerManager {
+    inner: Arc<Inner>,
+}
+
+/// Events of every peer of a `PeerManager`, returned by `PeerManager::new`
+pub struct PeerManagerEvents {
+    events: mpsc::Receiver<(u128, PeerEvent)>,
+}
+
+impl Stream for PeerManagerEvents {
+    type Item = (u128, PeerEvent);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+struct Inner {
+    peers: Mutex<HashMap<u128, ManagedPeer>>,
+    events: mpsc::Sender<(u128, PeerEvent)>,
+    removal_watchers: Mutex<Vec<mpsc::UnboundedSender<u128>>>,
+    shutting_down: AtomicBool,
+}
+
+impl Inner {
+    fn remove(&self, peer_id: u128) -> Option<ManagedPeer> {
+        let removed = self.peers.lock().unwrap().remove(&peer_id);
+        if removed.is_some() {
+            let mut watchers = self.removal_watchers.lock().unwrap();
+            watchers.retain(|watcher| watcher.send(peer_id).is_ok());
+        }
+        removed
+    }
+}
+
+struct ManagedPeer {
+    peer: Arc<tokio::sync::Mutex<Peer>>,
+    /// Open channels by label
+    channels: HashMap<String, DataChannel>,
+}
+
+impl PeerManager {
+    pub fn new() -> (PeerManager, PeerManagerEvents) {
+        let (events, events_rx) = mpsc::channel(MERGED_EVENT_CAPACITY);
+        let manager = PeerManager {
+            inner: Arc::new(Inner {
+                peers: Mutex::new(HashMap::new()),
+                events,
+                removal_watchers: Mutex::new(Vec::new()),
+                shutting_down: AtomicBool::new(false),
+            }),
+        };
+        (manager, PeerManagerEvents { events: events_rx })
+    }
+
+    /// Take over a peer created with `Peer::new_with_stream`, usually once its offer or answer
+    /// was handled. Its events show up on the manager's stream from now on.
+    /// Once `shutdown` was called the peer is closed instead.
+    pub fn insert(&self, mut peer: Peer, events: PeerEventStream) -> u128 {
+        let peer_id = peer.peer_id;
+        let mut peers = self.inner.peers.lock().unwrap();
+        if self.is_shutting_down() {
+            tokio::spawn(async move {
+                let _ = peer.close().await;
+            });
+            return peer_id;
+        }
+        peers.insert(
+            peer_id,
+            ManagedPeer {
+                peer: Arc::new(tokio::sync::Mutex::new(peer)),
+                channels: HashMap::new(),
+            },
+        );
+        drop(peers);
+        tokio::spawn(track_peer(Arc::downgrade(&self.inner), peer_id, events));
+        peer_id
+    }
+
+    /// For signaling or creating channels on a managed peer
+    pub fn get(&self, peer_id: u128) -> Option<Arc<tokio::sync::Mutex<Peer>>> {
+        let peers = self.inner.peers.lock().unwrap();
+        peers.get(&peer_id).map(|managed| managed.peer.clone())
+    }
+
+    pub fn peers(&self) -> Vec<u128> {
+        self.inner.peers.lock().unwrap().keys().copied().collect()
+    }
+
+    /// Labels of the open channels of a peer
+    pub fn channels(&self, peer_id: u128) -> Vec<String> {
+        let peers = self.inner.peers.lock().unwrap();
+        peers
+            .get(&peer_id)
+            .map(|managed| managed.channels.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn channel(&self, peer_id: u128, label: &str) -> Option<DataChannel> {
+        let peers = self.inner.peers.lock().unwrap();
+        peers
+            .get(&peer_id)
+            .and_then(|managed| managed.channels.get(label).cloned())
+    }
+
+    pub async fn send_to(&self, peer_id: u128, label: &str, data: &Bytes) -> Result<usize> {
+        let channel = self.channel(peer_id, label).ok_or_else(|| no_channel(peer_id, label))?;
+        channel.send(data).await.map_err(Error::Channel)
+    }
+
+    pub async fn send_text_to(&self, peer_id: u128, label: &str, text: &str) -> Result<usize> {
+        let channel = self.channel(peer_id, label).ok_or_else(|| no_channel(peer_id, label))?;
+        channel
+            .send_text(text.to_owned())
+            .await
+            .map_err(Error::Channel)
+    }
+
+    /// Send to the channel named `label` of every peer at once, returns how many sends succeeded
+    pub async fn broadcast(&self, label: &str, data: &Bytes) -> usize {
+        let mut sends = JoinSet::new();
+        for channel in self.channels_labelled(label) {
+            let data = data.clone();
+            sends.spawn(async move { channel.send(&data).await.is_ok() });
+        }
+        count_sent(sends).await
+    }
+
+    pub async fn broadcast_text(&self, label: &str, text: &str) -> usize {
+        let mut sends = JoinSet::new();
+        for channel in self.channels_labelled(label) {
+            let text = text.to_owned();
+            sends.spawn(async move { channel.send_text(text).await.is_ok() });
+        }
+        count_sent(sends).await
+    }
+
+    /// Close a peer and stop managing it
+    pub async fn remove(&self, peer_id: u128) -> Result<()> {
+        let removed = self.inner.remove(peer_id);
+        match removed {
+            Some(managed) => managed.peer.lock().await.close().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Stop accepting peers and close all managed ones gracefully, at the same time
+    pub async fn shutdown(&self, options: &ShutdownOptions) -> ShutdownReport {
+        let peer_ids: Vec<u128> = {
+            let peers = self.inner.peers.lock().unwrap();
+            // set under the lock so `insert` either sees it or its peer is closed below
+            self.inner.shutting_down.store(true, Ordering::SeqCst);
+            peers.keys().copied().collect()
+        };
+        let peers = peer_ids
+            .into_iter()
+            .filter_map(|peer_id| self.inner.remove(peer_id))
+            .map(|managed| managed.peer)
+            .collect();
+        shutdown::shutdown_peers(peers, options).await
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.inner.shutting_down.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn contains(&self, peer_id: u128) -> bool {
+        self.inner.peers.lock().unwrap().contains_key(&peer_id)
+    }
+
+    /// Ids of peers as they are removed, for state kept elsewhere about them
+    pub(crate) fn watch_removals(&self) -> mpsc::UnboundedReceiver<u128> {
+        let (watcher, removals) = mpsc::unbounded_channel();
+        self.inner.removal_watchers.lock().unwrap().push(watcher);
+        removals
+    }
+
+    fn channels_labelled(&self, label: &str) -> Vec<DataChannel> {
+        let peers = self.inner.peers.lock().unwrap();
+        peers
+            .values()
+            .filter_map(|managed| managed.channels.get(label).cloned())
+            .collect()
+    }
+}
+
+async fn count_sent(mut sends: JoinSet<bool>) -> usize {
+    let mut sent = 0;
+    while let Some(result) = sends.join_next().await {
+        if let Ok(true) = result {
+            sent += 1;
+        }
+    }
+    sent
+}
+
+fn no_channel(peer_id: u128, label: &str) -> Error {
+    Error::Channel(webrtc::Error::new(format!(
+        "peer {} has no open channel {}",
+        peer_id, label
+    )))
+}
+
+/// Keep the channel registry up to date and forward events until the peer's stream ends,
+/// then forget and close the peer
+async fn track_peer(inner: Weak<Inner>, peer_id: u128, mut events: PeerEventStream) {
+    while let Some(e) = events.next().await {
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            // the manager is gone and with it the peer
+            None => return,
+        };
+        if let PeerEvent::DataChannelStateChange(c) = &e {
+            let mut peers = inner.peers.lock().unwrap();
+            if let Some(managed) = peers.get_mut(&peer_id) {
+                if c.ready_state() == RTCDataChannelState::Open {
+                    managed.channels.insert(c.label().to_owned(), c.clone());
+                } else if managed
+                    .channels
+                    .get(c.label())
+                    .is_some_and(|open| Arc::ptr_eq(open, c))
+                {
+                    managed.channels.remove(c.label());
+                }
+            }
+        }
+        let _ = inner.events.send((peer_id, e)).await;
+    }
+
+    // closed or failed
+    let removed = match inner.upgrade() {
+        Some(inner) => inner.remove(peer_id),
+        None => None,
+    };
+    if let Some(managed) = removed {
+        let _ = managed.peer.lock().await.close().await;
+    }
+}
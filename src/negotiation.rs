@@ -0,0 +1,223 @@
+use crate::queue::EventSender;
+use crate::{Error, PeerEvent, Result, SdpCodec};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use tokio::sync::{mpsc, Mutex};
+use webrtc::ice_transport::ice_gathering_state::RTCIceGatheringState;
+use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::signaling_state::RTCSignalingState;
+use webrtc::peer_connection::RTCPeerConnection;
+
+pub(crate) type SendDescriptionFn =
+    Arc<dyn Fn(String) -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send + Sync>;
+
+/// Which side gives way when both peers send an offer at the same time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NegotiationRole {
+    /// Drops its own offer and answers the remote one. The native WebRTC stack cannot roll
+    /// back an offer, so a native polite peer only applies its offer once it is answered.
+    /// Candidates are only gathered from then on, so without trickle ICE it follows up its
+    /// first offer with another one carrying them.
+    Polite,
+    /// Ignores the remote offer and waits for the answer to its own
+    Impolite,
+}
+
+#[derive(Clone)]
+pub(crate) struct Negotiation {
+    pub(crate) role: NegotiationRole,
+    pub(crate) send_description: SendDescriptionFn,
+}
+
+/// Perfect negotiation, see https://w3c.github.io/webrtc-pc/#perfect-negotiation-example
+pub(crate) struct Negotiator {
+    negotiation: Negotiation,
+    peer_connection: Weak<RTCPeerConnection>,
+    trickle_ice: bool,
+    sdp_codec: Arc<dyn SdpCodec>,
+    events: EventSender,
+    /// Descriptions on their way to `send_description`, in the order they were made
+    outgoing: mpsc::UnboundedSender<String>,
+    making_offer: AtomicBool,
+    /// Negotiation was needed again while an offer was being made
+    needed_again: AtomicBool,
+    ignore_offer: AtomicBool,
+    /// The polite side's offer that was sent but not applied yet. Held while an offer is made or
+    /// a description applied, so the two never interleave.
+    unanswered_offer: Mutex<Option<RTCSessionDescription>>,
+}
+
+impl Negotiator {
+    pub(crate) fn new(
+        negotiation: Negotiation,
+        peer_connection: &Arc<RTCPeerConnection>,
+        trickle_ice: bool,
+        sdp_codec: Arc<dyn SdpCodec>,
+        events: EventSender,
+    ) -> Negotiator {
+        let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel();
+        let send_description = negotiation.send_description.clone();
+        let errors = events.clone();
+        // user code runs here, never while the negotiation state is locked
+        tokio::spawn(async move {
+            while let Some(description) = outgoing_rx.recv().await {
+                if let Err(e) = send_description(description).await {
+                    errors.send(PeerEvent::Error(e)).await;
+                }
+            }
+        });
+        Negotiator {
+            negotiation,
+            peer_connection: Arc::downgrade(peer_connection),
+            trickle_ice,
+            sdp_codec,
+            events,
+            outgoing,
+            making_offer: AtomicBool::new(false),
+            needed_again: AtomicBool::new(false),
+            ignore_offer: AtomicBool::new(false),
+            unanswered_offer: Mutex::new(None),
+        }
+    }
+
+    /// Send a new offer, called whenever channels or tracks were added
+    pub(crate) async fn negotiation_needed(&self) {
+        let peer_connection = match self.peer_connection.upgrade() {
+            Some(peer_connection) => peer_connection,
+            None => return,
+        };
+        if self.making_offer.swap(true, Ordering::SeqCst) {
+            self.needed_again.store(true, Ordering::SeqCst);
+            return;
+        }
+        loop {
+            if let Err(e) = self.make_offer(&peer_connection).await {
+                self.events.send(PeerEvent::Error(e)).await;
+            }
+            self.making_offer.store(false, Ordering::SeqCst);
+            if !self.needed_again.swap(false, Ordering::SeqCst)
+                || self.making_offer.swap(true, Ordering::SeqCst)
+            {
+                return;
+            }
+        }
+    }
+
+    async fn make_offer(&self, peer_connection: &RTCPeerConnection) -> Result<()> {
+        let mut unanswered_offer = self.unanswered_offer.lock().await;
+        // the connection asks again once it is back to stable, if there is still something to negotiate
+        if peer_connection.signaling_state() != RTCSignalingState::Stable
+            || unanswered_offer.is_some()
+        {
+            return Ok(());
+        }
+        let offer = peer_connection
+            .create_offer(None)
+            .await
+            .map_err(Error::Sdp)?;
+        let offer = match self.negotiation.role {
+            NegotiationRole::Impolite => {
+                crate::local_description(
+                    peer_connection,
+                    offer,
+                    self.trickle_ice,
+                    self.sdp_codec.as_ref(),
+                )
+                .await?
+            }
+            NegotiationRole::Polite => {
+                // candidates gathered so far are part of the offer, later ones are trickled
+                // once it is applied
+                let json = serde_json::to_string(&offer)
+                    .map_err(|e| Error::Sdp(webrtc::Error::new(e.to_string())))?;
+                let encoded = self.sdp_codec.encode(&json)?;
+                *unanswered_offer = Some(offer);
+                encoded
+            }
+        };
+        self.send(offer)
+    }
+
+    /// Queue `description` for `send_description`, behind everything made before it
+    fn send(&self, description: String) -> Result<()> {
+        self.outgoing
+            .send(description)
+            .map_err(|_| Error::Signaling("negotiation has stopped".to_owned()))
+    }
+
+    pub(crate) async fn receive_description(
+        &self,
+        peer_connection: &RTCPeerConnection,
+        description: &str,
+    ) -> Result<()> {
+        let desc_data = self.sdp_codec.decode(description)?;
+        let desc = serde_json::from_str::<RTCSessionDescription>(&desc_data)?;
+
+        let mut unanswered_offer = self.unanswered_offer.lock().await;
+        let is_offer = desc.sdp_type == RTCSdpType::Offer;
+        // an offer being made holds the lock, so it is either done or not started yet
+        let offer_collision = is_offer
+            && (unanswered_offer.is_some()
+                || peer_connection.signaling_state() != RTCSignalingState::Stable);
+        let ignore_offer =
+            self.negotiation.role == NegotiationRole::Impolite && offer_collision;
+        self.ignore_offer.store(ignore_offer, Ordering::SeqCst);
+        if ignore_offer {
+            return Ok(());
+        }
+
+        let mut gathering = None;
+        match unanswered_offer.take() {
+            // polite side gives up its offer in favour of the remote one, the connection asks
+            // for another once this one is answered if there is still something to negotiate
+            Some(_) if is_offer => {}
+            Some(offer) => {
+                // the offer went out before there were candidates to put in it
+                if !self.trickle_ice
+                    && peer_connection.ice_gathering_state() == RTCIceGatheringState::New
+                {
+                    gathering = Some(peer_connection.gathering_complete_promise().await);
+                }
+                peer_connection
+                    .set_local_description(offer)
+                    .await
+                    .map_err(Error::Sdp)?
+            }
+            None => {}
+        }
+        peer_connection
+            .set_remote_description(desc)
+            .await
+            .map_err(Error::Sdp)?;
+        if is_offer {
+            let answer = peer_connection
+                .create_answer(None)
+                .await
+                .map_err(Error::Sdp)?;
+            let answer = crate::local_description(
+                peer_connection,
+                answer,
+                self.trickle_ice,
+                self.sdp_codec.as_ref(),
+            )
+            .await?;
+            self.send(answer)?;
+        }
+        drop(unanswered_offer);
+
+        if let Some(mut gathering) = gathering {
+            let _ = gathering.recv().await;
+            // offers include the candidates gathered so far
+            self.negotiation_needed().await;
+        }
+        Ok(())
+    }
+
+    /// Candidates of an offer we ignored are expected to fail
+    pub(crate) fn ignoring_offer(&self) -> bool {
+        self.ignore_offer.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,47 @@
+use crate::DataChannel;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Sending waits while more than this is buffered
+const HIGH_WATER_MARK: usize = 1024 * 1024;
+/// and continues once the buffered amount drops below this
+const LOW_WATER_MARK: usize = 256 * 1024;
+/// In case the buffered amount low event is missed
+const BUFFER_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Paces sending on a channel by its buffered amount, so a fast sender does not queue up
+/// everything in memory
+#[derive(Clone)]
+pub(crate) struct SendPacer {
+    channel: DataChannel,
+    buffer_low: Arc<Notify>,
+}
+
+impl SendPacer {
+    /// Takes over the buffered amount low event of `channel`
+    pub(crate) async fn new(channel: DataChannel) -> SendPacer {
+        let buffer_low = Arc::new(Notify::new());
+        let notify = buffer_low.clone();
+        channel
+            .set_buffered_amount_low_threshold(LOW_WATER_MARK)
+            .await;
+        channel
+            .on_buffered_amount_low(Box::new(move || {
+                notify.notify_one();
+                Box::pin(async {})
+            }))
+            .await;
+        SendPacer {
+            channel,
+            buffer_low,
+        }
+    }
+
+    /// Wait until the channel has room for more data
+    pub(crate) async fn wait_for_room(&self) {
+        while self.channel.buffered_amount().await > HIGH_WATER_MARK {
+            let _ = tokio::time::timeout(BUFFER_POLL_INTERVAL, self.buffer_low.notified()).await;
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use crate::PeerEvent;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+use tokio::sync::Notify;
+use webrtc::peer_connection::RTCPeerConnection;
+
+/// What happens to new events when a bounded event queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackpressurePolicy {
+    /// Wait for room in the queue, this stalls the SCTP reader of the connection
+    Block,
+    /// Discard the oldest queued event to make room
+    DropOldest,
+    /// Discard the incoming event
+    DropNewest,
+    /// Close the peer connection
+    ClosePeer,
+}
+
+struct QueueState<T> {
+    events: VecDeque<T>,
+    closed: bool,
+    /// No new events are taken, the stream ends once the queued ones are delivered
+    finishing: bool,
+    reader: Option<Waker>,
+}
+
+/// Event queue between the RTCPeerConnection hooks and the single consumer of a peer's events,
+/// also used by channel wrappers for what they receive
+pub(crate) struct EventQueue<T = PeerEvent> {
+    state: Mutex<QueueState<T>>,
+    capacity: Option<usize>,
+    policy: BackpressurePolicy,
+    dropped: AtomicU64,
+    writable: Notify,
+}
+
+impl<T> EventQueue<T> {
+    pub(crate) fn new(capacity: Option<usize>, policy: BackpressurePolicy) -> EventQueue<T> {
+        EventQueue {
+            state: Mutex::new(QueueState {
+                events: VecDeque::new(),
+                closed: false,
+                finishing: false,
+                reader: None,
+            }),
+            capacity,
+            policy,
+            dropped: AtomicU64::new(0),
+            writable: Notify::new(),
+        }
+    }
+
+    /// Queue an event, returns false if the queue overflowed under `BackpressurePolicy::ClosePeer`
+    pub(crate) async fn push(&self, event: T) -> bool {
+        let mut event = Some(event);
+        loop {
+            // registered before checking so a pop in between is not missed
+            let writable = self.writable.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.closed || state.finishing {
+                    // late events after close are dropped quietly
+                    return true;
+                }
+                let full = matches!(self.capacity, Some(capacity) if state.events.len() >= capacity);
+                if full {
+                    match self.policy {
+                        BackpressurePolicy::Block => {}
+                        BackpressurePolicy::DropOldest => {
+                            state.events.pop_front();
+                            self.dropped.fetch_add(1, Ordering::Relaxed);
+                        }
+                        BackpressurePolicy::DropNewest => {
+                            self.dropped.fetch_add(1, Ordering::Relaxed);
+                            return true;
+                        }
+                        BackpressurePolicy::ClosePeer => {
+                            self.dropped.fetch_add(1, Ordering::Relaxed);
+                            return false;
+                        }
+                    }
+                }
+                if !full || self.policy != BackpressurePolicy::Block {
+                    state.events.push_back(event.take().unwrap());
+                    if let Some(reader) = state.reader.take() {
+                        reader.wake();
+                    }
+                    return true;
+                }
+            }
+            writable.await;
+        }
+    }
+
+    pub(crate) fn poll_pop(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Poll::Ready(None);
+        }
+        match state.events.pop_front() {
+            Some(event) => {
+                self.writable.notify_waiters();
+                Poll::Ready(Some(event))
+            }
+            None if state.finishing => {
+                state.closed = true;
+                Poll::Ready(None)
+            }
+            None => {
+                state.reader = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Stop delivering events, queued ones are discarded and blocked senders released
+    pub(crate) fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        state.events.clear();
+        if let Some(reader) = state.reader.take() {
+            reader.wake();
+        }
+        self.writable.notify_waiters();
+    }
+
+    /// Stop taking events and end the stream once the queued ones are delivered
+    pub(crate) fn finish(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.finishing = true;
+        if let Some(reader) = state.reader.take() {
+            reader.wake();
+        }
+        self.writable.notify_waiters();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.closed || state.finishing
+    }
+
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// An empty queue with the same bound and policy
+    pub(crate) fn with_same_bound<U>(&self) -> EventQueue<U> {
+        EventQueue::new(self.capacity, self.policy)
+    }
+}
+
+/// Handle used by the RTCPeerConnection hooks to deliver events
+pub(crate) struct EventSender<T = PeerEvent> {
+    queue: Arc<EventQueue<T>>,
+    peer_connection: Weak<RTCPeerConnection>,
+}
+
+impl<T> Clone for EventSender<T> {
+    fn clone(&self) -> Self {
+        EventSender {
+            queue: self.queue.clone(),
+            peer_connection: self.peer_connection.clone(),
+        }
+    }
+}
+
+impl<T> EventSender<T> {
+    pub(crate) fn new(queue: Arc<EventQueue<T>>, peer_connection: &Arc<RTCPeerConnection>) -> EventSender<T> {
+        EventSender {
+            queue,
+            peer_connection: Arc::downgrade(peer_connection),
+        }
+    }
+
+    pub(crate) async fn send(&self, event: T) {
+        if !self.queue.push(event).await {
+            self.queue.close();
+            if let Some(peer_connection) = self.peer_connection.upgrade() {
+                tokio::spawn(async move {
+                    let _ = peer_connection.close().await;
+                });
+            }
+        }
+    }
+
+    pub(crate) fn queue(&self) -> &Arc<EventQueue<T>> {
+        &self.queue
+    }
+
+    pub(crate) fn close(&self) {
+        self.queue.close();
+    }
+
+    pub(crate) fn finish(&self) {
+        self.queue.finish();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.queue.is_closed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::poll_fn;
+    use std::time::Duration;
+
+    fn event(n: u64) -> PeerEvent {
+        PeerEvent::Heartbeat(Duration::from_millis(n))
+    }
+
+    async fn pop(queue: &EventQueue) -> Option<u64> {
+        match poll_fn(|cx| queue.poll_pop(cx)).await {
+            Some(PeerEvent::Heartbeat(n)) => Some(n.as_millis() as u64),
+            Some(_) => panic!("unexpected event"),
+            None => None,
+        }
+    }
+
+    async fn queued(queue: &EventQueue) -> Vec<u64> {
+        queue.finish();
+        let mut events = vec![];
+        while let Some(n) = pop(queue).await {
+            events.push(n);
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn unbounded_keeps_everything() {
+        let queue = EventQueue::new(None, BackpressurePolicy::DropNewest);
+        for n in 0..100 {
+            assert!(queue.push(event(n)).await);
+        }
+        assert_eq!(queued(&queue).await, (0..100).collect::<Vec<_>>());
+        assert_eq!(queue.dropped(), 0);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_the_latest() {
+        let queue = EventQueue::new(Some(2), BackpressurePolicy::DropOldest);
+        for n in 0..5 {
+            assert!(queue.push(event(n)).await);
+        }
+        assert_eq!(queued(&queue).await, vec![3, 4]);
+        assert_eq!(queue.dropped(), 3);
+    }
+
+    #[tokio::test]
+    async fn drop_newest_keeps_the_first() {
+        let queue = EventQueue::new(Some(2), BackpressurePolicy::DropNewest);
+        for n in 0..5 {
+            assert!(queue.push(event(n)).await);
+        }
+        assert_eq!(queued(&queue).await, vec![0, 1]);
+        assert_eq!(queue.dropped(), 3);
+    }
+
+    #[tokio::test]
+    async fn close_peer_reports_the_overflow() {
+        let queue = EventQueue::new(Some(1), BackpressurePolicy::ClosePeer);
+        assert!(queue.push(event(0)).await);
+        assert!(!queue.push(event(1)).await);
+        assert_eq!(queue.dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_room() {
+        let queue = Arc::new(EventQueue::new(Some(1), BackpressurePolicy::Block));
+        assert!(queue.push(event(0)).await);
+
+        let sender = queue.clone();
+        let blocked = tokio::spawn(async move { sender.push(event(1)).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!blocked.is_finished());
+
+        assert_eq!(pop(&queue).await, Some(0));
+        assert!(blocked.await.unwrap());
+        assert_eq!(queued(&queue).await, vec![1]);
+        assert_eq!(queue.dropped(), 0);
+    }
+
+    #[tokio::test]
+    async fn close_releases_blocked_senders() {
+        let queue = Arc::new(EventQueue::new(Some(1), BackpressurePolicy::Block));
+        assert!(queue.push(event(0)).await);
+
+        let sender = queue.clone();
+        let blocked = tokio::spawn(async move { sender.push(event(1)).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        queue.close();
+        assert!(blocked.await.unwrap());
+        assert_eq!(pop(&queue).await, None);
+    }
+}
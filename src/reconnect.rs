@@ -0,0 +1,128 @@
+use crate::queue::EventSender;
+use crate::{PeerEvent, Result, SdpCodec};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::RTCPeerConnection;
+
+pub(crate) type SignalFn =
+    Arc<dyn Fn(String) -> Pin<Box<dyn Future<Output = Result<String>> + Send>> + Send + Sync>;
+
+/// How often and how fast a disconnected peer retries an ICE restart
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    pub max_attempts: u32,
+    /// Wait before the first attempt, doubled after every attempt
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Reconnect {
+    pub(crate) policy: ReconnectPolicy,
+    pub(crate) signal: SignalFn,
+}
+
+/// Runs ICE restarts through the user's signaling callback while the connection is down
+pub(crate) struct Reconnector {
+    reconnect: Reconnect,
+    peer_connection: Weak<RTCPeerConnection>,
+    trickle_ice: bool,
+    sdp_codec: Arc<dyn SdpCodec>,
+    events: EventSender,
+    running: AtomicBool,
+}
+
+impl Reconnector {
+    pub(crate) fn new(
+        reconnect: Reconnect,
+        peer_connection: &Arc<RTCPeerConnection>,
+        trickle_ice: bool,
+        sdp_codec: Arc<dyn SdpCodec>,
+        events: EventSender,
+    ) -> Reconnector {
+        Reconnector {
+            reconnect,
+            peer_connection: Arc::downgrade(peer_connection),
+            trickle_ice,
+            sdp_codec,
+            events,
+            running: AtomicBool::new(false),
+        }
+    }
+
+    /// Start reconnecting unless an earlier disconnect is already being handled
+    pub(crate) fn start(self: &Arc<Self>) {
+        if self.running.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let reconnector = self.clone();
+        tokio::spawn(async move {
+            let connected = reconnector.run().await;
+            reconnector.running.store(false, Ordering::SeqCst);
+            if !connected {
+                // out of attempts, give up like a peer without a reconnect policy
+                reconnector.events.close();
+            }
+        });
+    }
+
+    async fn run(&self) -> bool {
+        let policy = &self.reconnect.policy;
+        let mut backoff = policy.initial_backoff;
+        for _ in 0..policy.max_attempts {
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(policy.max_backoff);
+
+            let peer_connection = match self.peer_connection.upgrade() {
+                Some(peer_connection) => peer_connection,
+                None => return true,
+            };
+            if is_settled(&peer_connection) {
+                return true;
+            }
+
+            if let Err(e) = self.restart(&peer_connection).await {
+                self.events.send(PeerEvent::Error(e)).await;
+            }
+        }
+        match self.peer_connection.upgrade() {
+            Some(peer_connection) => is_settled(&peer_connection),
+            None => true,
+        }
+    }
+
+    async fn restart(&self, peer_connection: &RTCPeerConnection) -> Result<()> {
+        let offer =
+            crate::ice_restart_offer(peer_connection, self.trickle_ice, self.sdp_codec.as_ref())
+                .await?;
+        let answer = (self.reconnect.signal)(offer).await?;
+        crate::apply_answer(peer_connection, &answer, self.sdp_codec.as_ref()).await
+    }
+}
+
+/// Connected again, or closed on purpose so there is nothing to recover.
+/// The ICE state is checked as well since the peer connection state is not updated after a restart.
+fn is_settled(peer_connection: &RTCPeerConnection) -> bool {
+    matches!(
+        peer_connection.connection_state(),
+        RTCPeerConnectionState::Connected | RTCPeerConnectionState::Closed
+    ) || matches!(
+        peer_connection.ice_connection_state(),
+        RTCIceConnectionState::Connected | RTCIceConnectionState::Completed
+    )
+}
@@ -0,0 +1,269 @@
+use crate::{DataChannel, PeerManager};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+use tokio_stream::Stream;
+
+/// How many sends of a single publish are in flight at once by default
+const DEFAULT_MAX_CONCURRENT_SENDS: usize = 32;
+
+/// Named groups of the peers of a `PeerManager` to publish messages to.
+/// Peers leave all their rooms once the manager removes them, empty rooms are forgotten.
+/// Clones share the same rooms.
+#[derive(Clone)]
+pub struct Rooms {
+    manager: PeerManager,
+    state: Arc<Mutex<State>>,
+    events: mpsc::UnboundedSender<RoomEvent>,
+    max_concurrent_sends: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoomEvent {
+    Joined {
+        room: String,
+        peer_id: u128,
+    },
+    /// Also sent when the peer was removed from the manager
+    Left {
+        room: String,
+        peer_id: u128,
+    },
+}
+
+/// Membership changes of all rooms, returned by `Rooms::new`
+pub struct RoomEvents {
+    events: mpsc::UnboundedReceiver<RoomEvent>,
+}
+
+impl Stream for RoomEvents {
+    type Item = RoomEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// Members of every room and whether they receive their own messages
+    rooms: HashMap<String, HashMap<u128, bool>>,
+}
+
+impl State {
+    fn leave(&mut self, room: &str, peer_id: u128) -> bool {
+        let members = match self.rooms.get_mut(room) {
+            Some(members) => members,
+            None => return false,
+        };
+        let left = members.remove(&peer_id).is_some();
+        if members.is_empty() {
+            self.rooms.remove(room);
+        }
+        left
+    }
+}
+
+impl Rooms {
+    pub fn new(manager: &PeerManager) -> (Rooms, RoomEvents) {
+        let (events, events_rx) = mpsc::unbounded_channel();
+        let state = Arc::new(Mutex::new(State::default()));
+        tokio::spawn(leave_removed_peers(
+            Arc::downgrade(&state),
+            events.clone(),
+            manager.watch_removals(),
+        ));
+        let rooms = Rooms {
+            manager: manager.clone(),
+            state,
+            events,
+            max_concurrent_sends: DEFAULT_MAX_CONCURRENT_SENDS,
+        };
+        (rooms, RoomEvents { events: events_rx })
+    }
+
+    /// Limit how many members a single publish sends to at once, 32 by default
+    pub fn max_concurrent_sends(mut self, max_concurrent_sends: usize) -> Rooms {
+        self.max_concurrent_sends = max_concurrent_sends.max(1);
+        self
+    }
+
+    /// Add a managed peer to `room`, creating the room if needed. Returns false if the peer
+    /// already is a member or is not managed by the manager.
+    pub fn join(&self, room: &str, peer_id: u128) -> bool {
+        let mut state = self.state.lock().unwrap();
+        // checked under the lock so a concurrent removal is seen by `leave_removed_peers`
+        if !self.manager.contains(peer_id) {
+            return false;
+        }
+        let members = state.rooms.entry(room.to_owned()).or_default();
+        if members.contains_key(&peer_id) {
+            return false;
+        }
+        members.insert(peer_id, true);
+        let _ = self.events.send(RoomEvent::Joined {
+            room: room.to_owned(),
+            peer_id,
+        });
+        true
+    }
+
+    /// Returns false if the peer was not a member
+    pub fn leave(&self, room: &str, peer_id: u128) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if !state.leave(room, peer_id) {
+            return false;
+        }
+        let _ = self.events.send(RoomEvent::Left {
+            room: room.to_owned(),
+            peer_id,
+        });
+        true
+    }
+
+    /// Whether messages a member publishes to `room` are sent back to it, on by default.
+    /// Returns false if the peer is not a member.
+    pub fn set_echo(&self, room: &str, peer_id: u128, echo: bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match state
+            .rooms
+            .get_mut(room)
+            .and_then(|members| members.get_mut(&peer_id))
+        {
+            Some(member_echo) => {
+                *member_echo = echo;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn rooms(&self) -> Vec<String> {
+        self.state.lock().unwrap().rooms.keys().cloned().collect()
+    }
+
+    pub fn members(&self, room: &str) -> Vec<u128> {
+        let state = self.state.lock().unwrap();
+        state
+            .rooms
+            .get(room)
+            .map(|members| members.keys().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Rooms `peer_id` is a member of
+    pub fn rooms_of(&self, peer_id: u128) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state
+            .rooms
+            .iter()
+            .filter(|(_, members)| members.contains_key(&peer_id))
+            .map(|(room, _)| room.clone())
+            .collect()
+    }
+
+    /// Send to the channel named `label` of every member of `room`, returns how many sends
+    /// succeeded. `from` is the publishing member, skipped if it turned off echo.
+    /// Members without an open `label` channel are skipped.
+    pub async fn publish(
+        &self,
+        room: &str,
+        from: Option<u128>,
+        label: &str,
+        data: &Bytes,
+    ) -> usize {
+        let data = data.clone();
+        self.fan_out(self.recipients(room, from, label), move |channel| {
+            let data = data.clone();
+            async move { channel.send(&data).await.is_ok() }
+        })
+        .await
+    }
+
+    pub async fn publish_text(
+        &self,
+        room: &str,
+        from: Option<u128>,
+        label: &str,
+        text: &str,
+    ) -> usize {
+        let text = text.to_owned();
+        self.fan_out(self.recipients(room, from, label), move |channel| {
+            let text = text.clone();
+            async move { channel.send_text(text).await.is_ok() }
+        })
+        .await
+    }
+
+    fn recipients(&self, room: &str, from: Option<u128>, label: &str) -> Vec<DataChannel> {
+        let members: Vec<u128> = {
+            let state = self.state.lock().unwrap();
+            match state.rooms.get(room) {
+                Some(members) => members
+                    .iter()
+                    .filter(|(peer_id, echo)| **echo || Some(**peer_id) != from)
+                    .map(|(peer_id, _)| *peer_id)
+                    .collect(),
+                None => return Vec::new(),
+            }
+        };
+        members
+            .into_iter()
+            .filter_map(|peer_id| self.manager.channel(peer_id, label))
+            .collect()
+    }
+
+    /// Run `send` for every channel, at most `max_concurrent_sends` at a time
+    async fn fan_out<F, Fut>(&self, channels: Vec<DataChannel>, send: F) -> usize
+    where
+        F: Fn(DataChannel) -> Fut,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        let mut sends = JoinSet::new();
+        let mut sent = 0;
+        for channel in channels {
+            if sends.len() >= self.max_concurrent_sends {
+                if let Some(Ok(true)) = sends.join_next().await {
+                    sent += 1;
+                }
+            }
+            sends.spawn(send(channel));
+        }
+        while let Some(result) = sends.join_next().await {
+            if let Ok(true) = result {
+                sent += 1;
+            }
+        }
+        sent
+    }
+}
+
+/// Take peers the manager removed out of every room until the rooms are dropped
+async fn leave_removed_peers(
+    state: Weak<Mutex<State>>,
+    events: mpsc::UnboundedSender<RoomEvent>,
+    mut removals: mpsc::UnboundedReceiver<u128>,
+) {
+    while let Some(peer_id) = removals.recv().await {
+        let state = match state.upgrade() {
+            Some(state) => state,
+            None => return,
+        };
+        let mut state = state.lock().unwrap();
+        let rooms: Vec<String> = state
+            .rooms
+            .iter()
+            .filter(|(_, members)| members.contains_key(&peer_id))
+            .map(|(room, _)| room.clone())
+            .collect();
+        for room in rooms {
+            state.leave(&room, peer_id);
+            let _ = events.send(RoomEvent::Left { room, peer_id });
+        }
+    }
+}
@@ -0,0 +1,427 @@
+//! Request/response calls over a data channel, see `RpcChannel`.
+//!
+//! Messages are JSON text tagged by `type`, the same format `RpcChannel` in
+//! `cyberdeck-client-web-sys` speaks:
+//!
+//! ```text
+//! {"type":"request","id":1,"method":"join","params":{"room":"lobby"}}
+//! {"type":"response","id":1,"result":["alice","bob"]}
+//! {"type":"response","id":1,"error":{"code":-32601,"message":"unknown method join"}}
+//! {"type":"cancel","id":1}
+//! ```
+//!
+//! Ids are chosen by the caller and only need to be unique among its own calls in flight.
+//! Both sides can make calls on the same channel.
+
+use crate::{DataChannel, Error, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio::task::AbortHandle;
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+
+/// How long `RpcChannel::call` waits for a response unless told otherwise
+pub const DEFAULT_RPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RpcMessage {
+    Request {
+        id: u64,
+        method: String,
+        #[serde(default)]
+        params: Value,
+    },
+    /// Answer to the request with the same id, carrying either `result` or `error`
+    Response {
+        id: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        result: Option<Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<RpcError>,
+    },
+    /// The caller lost interest, its handler is stopped and nothing is sent back
+    Cancel { id: u64 },
+}
+
+/// Error reply of a call, with the codes of JSON-RPC for failures of the RPC layer itself
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const INTERNAL_ERROR: i64 = -32603;
+
+    pub fn new(code: i64, message: impl Into<String>) -> RpcError {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn internal(message: impl Into<String>) -> RpcError {
+        RpcError::new(RpcError::INTERNAL_ERROR, message)
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (code {})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+type HandlerFuture = Pin<Box<dyn Future<Output = Result<Value, RpcError>> + Send>>;
+type Handler = Arc<dyn Fn(Value) -> HandlerFuture + Send + Sync>;
+
+/// Async handlers by method name, answering the calls of the other side
+#[derive(Clone, Default)]
+pub struct RpcRouter {
+    methods: HashMap<String, Handler>,
+}
+
+impl RpcRouter {
+    pub fn new() -> RpcRouter {
+        RpcRouter::default()
+    }
+
+    /// Handle calls of `name`. Params that do not deserialize as `P` are answered with
+    /// `RpcError::INVALID_PARAMS` without calling `handler`.
+    pub fn method<P, R, F, Fut>(mut self, name: &str, handler: F) -> RpcRouter
+    where
+        P: DeserializeOwned,
+        R: Serialize,
+        F: Fn(P) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, RpcError>> + Send + 'static,
+    {
+        let handler = Arc::new(handler);
+        self.methods.insert(
+            name.to_string(),
+            Arc::new(move |params: Value| -> HandlerFuture {
+                let params = match serde_json::from_value(params) {
+                    Ok(params) => params,
+                    Err(e) => {
+                        let error = RpcError::new(RpcError::INVALID_PARAMS, e.to_string());
+                        return Box::pin(async move { Err(error) });
+                    }
+                };
+                let result = handler(params);
+                Box::pin(async move {
+                    let result = result.await?;
+                    serde_json::to_value(result).map_err(|e| RpcError::internal(e.to_string()))
+                })
+            }),
+        );
+        self
+    }
+
+    /// Run the handler of `method`, unknown methods fail with `RpcError::METHOD_NOT_FOUND`
+    fn handle(&self, method: &str, params: Value) -> HandlerFuture {
+        match self.methods.get(method) {
+            Some(handler) => handler(params),
+            None => {
+                let error = RpcError::new(
+                    RpcError::METHOD_NOT_FOUND,
+                    format!("unknown method {}", method),
+                );
+                Box::pin(async move { Err(error) })
+            }
+        }
+    }
+}
+
+struct Inner {
+    channel: DataChannel,
+    next_id: AtomicU64,
+    /// Calls waiting for their response, `None` once the channel closed
+    pending: Mutex<Option<HashMap<u64, oneshot::Sender<Result<Value>>>>>,
+    /// Handlers still working on a request of the other side
+    running: Mutex<HashMap<u64, AbortHandle>>,
+}
+
+impl Inner {
+    async fn send(&self, message: &RpcMessage) -> Result<()> {
+        let text =
+            serde_json::to_string(message).map_err(|e| Error::MessageEncode(e.to_string()))?;
+        self.channel.send_text(text).await.map_err(Error::Channel)?;
+        Ok(())
+    }
+
+    fn receive(self: &Arc<Self>, router: &RpcRouter, message: RpcMessage) {
+        match message {
+            RpcMessage::Request { id, method, params } => {
+                let handling = tokio::spawn(router.handle(&method, params));
+                self.running
+                    .lock()
+                    .unwrap()
+                    .insert(id, handling.abort_handle());
+                let inner = self.clone();
+                tokio::spawn(async move {
+                    let result = match handling.await {
+                        Ok(result) => result,
+                        Err(e) if e.is_cancelled() => return,
+                        Err(_) => Err(RpcError::internal(format!("handler of {} panicked", method))),
+                    };
+                    inner.running.lock().unwrap().remove(&id);
+                    inner.respond(id, result).await;
+                });
+            }
+            RpcMessage::Response { id, result, error } => {
+                let call = self
+                    .pending
+                    .lock()
+                    .unwrap()
+                    .as_mut()
+                    .and_then(|p| p.remove(&id));
+                if let Some(call) = call {
+                    let _ = call.send(match error {
+                        Some(error) => Err(Error::Rpc(error)),
+                        // a `null` result does not survive as `Some`
+                        None => Ok(result.unwrap_or(Value::Null)),
+                    });
+                }
+            }
+            RpcMessage::Cancel { id } => {
+                if let Some(handling) = self.running.lock().unwrap().remove(&id) {
+                    handling.abort();
+                }
+            }
+        }
+    }
+
+    async fn respond(self: Arc<Self>, id: u64, result: Result<Value, RpcError>) {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        let _ = self.send(&RpcMessage::Response { id, result, error }).await;
+    }
+}
+
+/// Owned by the message handler of the channel, which is dropped once the channel closes
+struct CloseOnDrop(Arc<Inner>);
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        // waiting calls fail with `Error::ChannelClosed`
+        self.0.pending.lock().unwrap().take();
+        for (_, handling) in self.0.running.lock().unwrap().drain() {
+            handling.abort();
+        }
+    }
+}
+
+/// Removes a call that is dropped before its response arrived and tells the other side
+struct PendingCall {
+    inner: Arc<Inner>,
+    id: u64,
+}
+
+impl Drop for PendingCall {
+    fn drop(&mut self) {
+        let waiting = self
+            .inner
+            .pending
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|pending| pending.remove(&self.id));
+        if waiting.is_some() {
+            let inner = self.inner.clone();
+            let id = self.id;
+            tokio::spawn(async move {
+                let _ = inner.send(&RpcMessage::Cancel { id }).await;
+            });
+        }
+    }
+}
+
+/// Calls methods of the other side of a data channel and answers its calls with an `RpcRouter`,
+/// one of the [channel wrappers](crate#channel-wrappers). Any number of calls can be in flight
+/// at once, dropping one before it finished cancels it on the other side.
+#[derive(Clone)]
+pub struct RpcChannel {
+    inner: Arc<Inner>,
+    timeout: Duration,
+}
+
+impl RpcChannel {
+    /// Pass `RpcRouter::new()` for a side that only makes calls
+    pub fn new(channel: DataChannel, router: RpcRouter) -> RpcChannel {
+        let inner = Arc::new(Inner {
+            channel: channel.clone(),
+            next_id: AtomicU64::new(1),
+            pending: Mutex::new(Some(HashMap::new())),
+            running: Mutex::new(HashMap::new()),
+        });
+        let receiver = CloseOnDrop(inner.clone());
+        channel.on_message(Box::new(move |msg: DataChannelMessage| {
+            // anything else on the channel is ignored
+            if let Ok(message) = serde_json::from_slice::<RpcMessage>(&msg.data) {
+                receiver.0.receive(&router, message);
+            }
+            Box::pin(async {})
+        }));
+        RpcChannel {
+            inner,
+            timeout: DEFAULT_RPC_TIMEOUT,
+        }
+    }
+
+    /// How long calls wait for their response before failing with `Error::Timeout`,
+    /// `DEFAULT_RPC_TIMEOUT` by default
+    pub fn timeout(mut self, timeout: Duration) -> RpcChannel {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Call `method` of the other side. Error replies come back as `Error::Rpc`.
+    pub async fn call<P, R>(&self, method: &str, params: &P) -> Result<R>
+    where
+        P: Serialize + ?Sized,
+        R: DeserializeOwned,
+    {
+        self.call_with_timeout(method, params, self.timeout).await
+    }
+
+    /// Like `call` but with its own timeout, after which the call is cancelled
+    pub async fn call_with_timeout<P, R>(
+        &self,
+        method: &str,
+        params: &P,
+        timeout: Duration,
+    ) -> Result<R>
+    where
+        P: Serialize + ?Sized,
+        R: DeserializeOwned,
+    {
+        let params =
+            serde_json::to_value(params).map_err(|e| Error::MessageEncode(e.to_string()))?;
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let (response, response_rx) = oneshot::channel();
+        match self.inner.pending.lock().unwrap().as_mut() {
+            Some(pending) => pending.insert(id, response),
+            None => return Err(Error::ChannelClosed),
+        };
+        let _call = PendingCall {
+            inner: self.inner.clone(),
+            id,
+        };
+
+        let request = RpcMessage::Request {
+            id,
+            method: method.to_string(),
+            params,
+        };
+        self.inner.send(&request).await?;
+        let result = match tokio::time::timeout(timeout, response_rx).await {
+            Ok(Ok(result)) => result?,
+            Ok(Err(_)) => return Err(Error::ChannelClosed),
+            Err(_) => return Err(Error::Timeout),
+        };
+        serde_json::from_value(result).map_err(|e| Error::MessageDecode(e.to_string()))
+    }
+
+    pub fn channel(&self) -> &DataChannel {
+        &self.inner.channel
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn roundtrip(message: RpcMessage, text: &str) {
+        assert_eq!(serde_json::to_string(&message).unwrap(), text);
+        assert_eq!(serde_json::from_str::<RpcMessage>(text).unwrap(), message);
+    }
+
+    #[test]
+    fn messages_use_the_documented_format() {
+        roundtrip(
+            RpcMessage::Request {
+                id: 1,
+                method: "join".to_owned(),
+                params: json!({"room": "lobby"}),
+            },
+            r#"{"type":"request","id":1,"method":"join","params":{"room":"lobby"}}"#,
+        );
+        roundtrip(
+            RpcMessage::Response {
+                id: 1,
+                result: Some(json!(["alice", "bob"])),
+                error: None,
+            },
+            r#"{"type":"response","id":1,"result":["alice","bob"]}"#,
+        );
+        roundtrip(
+            RpcMessage::Response {
+                id: 1,
+                result: None,
+                error: Some(RpcError::new(
+                    RpcError::METHOD_NOT_FOUND,
+                    "unknown method join",
+                )),
+            },
+            r#"{"type":"response","id":1,"error":{"code":-32601,"message":"unknown method join"}}"#,
+        );
+        roundtrip(RpcMessage::Cancel { id: 1 }, r#"{"type":"cancel","id":1}"#);
+    }
+
+    #[test]
+    fn missing_params_are_null() {
+        let message: RpcMessage =
+            serde_json::from_str(r#"{"type":"request","id":7,"method":"ping"}"#).unwrap();
+        assert_eq!(
+            message,
+            RpcMessage::Request {
+                id: 7,
+                method: "ping".to_owned(),
+                params: Value::Null,
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_message_types_are_rejected() {
+        assert!(serde_json::from_str::<RpcMessage>(r#"{"type":"notify","id":1}"#).is_err());
+        assert!(serde_json::from_str::<RpcMessage>(r#"{"id":1,"method":"ping"}"#).is_err());
+    }
+
+    fn router() -> RpcRouter {
+        RpcRouter::new().method("add", |(a, b): (i64, i64)| async move { Ok(a + b) })
+    }
+
+    #[tokio::test]
+    async fn router_calls_the_handler() {
+        let result = router().handle("add", json!([2, 3])).await;
+        assert_eq!(result, Ok(json!(5)));
+    }
+
+    #[tokio::test]
+    async fn unknown_methods_are_not_found() {
+        let error = router().handle("sub", json!([2, 3])).await.unwrap_err();
+        assert_eq!(error.code, RpcError::METHOD_NOT_FOUND);
+        assert_eq!(error.message, "unknown method sub");
+    }
+
+    #[tokio::test]
+    async fn mismatched_params_are_invalid() {
+        let error = router().handle("add", json!({"a": 2})).await.unwrap_err();
+        assert_eq!(error.code, RpcError::INVALID_PARAMS);
+    }
+}
@@ -0,0 +1,85 @@
+use crate::{DataChannel, Peer};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+/// How often buffered data is checked while flushing
+const FLUSH_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How peers are closed by `Peer::close_gracefully`, `PeerManager::shutdown` and
+/// `ServerShutdown::shutdown`
+#[derive(Debug, Clone)]
+pub struct ShutdownOptions {
+    pub(crate) goodbye: Option<String>,
+    pub(crate) deadline: Duration,
+}
+
+impl ShutdownOptions {
+    /// No goodbye message and 5 seconds to flush
+    pub fn new() -> ShutdownOptions {
+        ShutdownOptions {
+            goodbye: None,
+            deadline: Duration::from_secs(5),
+        }
+    }
+
+    /// Text sent on every open channel before closing
+    pub fn goodbye(mut self, goodbye: &str) -> ShutdownOptions {
+        self.goodbye = Some(goodbye.to_owned());
+        self
+    }
+
+    /// How long to wait for buffered data to be sent, connections are closed after it either way
+    pub fn deadline(mut self, deadline: Duration) -> ShutdownOptions {
+        self.deadline = deadline;
+        self
+    }
+}
+
+impl Default for ShutdownOptions {
+    fn default() -> Self {
+        ShutdownOptions::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Peers that were open when the shutdown started
+    pub peers: usize,
+    /// Peers whose buffered data was sent before the deadline and that closed without error
+    pub closed_cleanly: usize,
+}
+
+/// Wait until no channel has data buffered
+pub(crate) async fn flush(channels: &[DataChannel]) {
+    for channel in channels {
+        while channel.buffered_amount().await > 0 {
+            tokio::time::sleep(FLUSH_POLL_INTERVAL).await;
+        }
+    }
+}
+
+/// Close all `peers` gracefully at once
+pub(crate) async fn shutdown_peers(
+    peers: Vec<Arc<tokio::sync::Mutex<Peer>>>,
+    options: &ShutdownOptions,
+) -> ShutdownReport {
+    let mut report = ShutdownReport {
+        peers: peers.len(),
+        closed_cleanly: 0,
+    };
+    let mut closes = JoinSet::new();
+    for peer in peers {
+        let options = options.clone();
+        closes.spawn(async move {
+            let mut peer = peer.lock().await;
+            matches!(peer.close_gracefully(&options).await, Ok(true))
+        });
+    }
+    while let Some(result) = closes.join_next().await {
+        if let Ok(true) = result {
+            report.closed_cleanly += 1;
+        }
+    }
+    report
+}
@@ -0,0 +1,252 @@
+//! Client of the `/connect` endpoint served by `signaling::server` and `examples/signaling_server.rs`.
+
+use super::{Description, Signal, Signaler};
+use crate::{Error, Peer, Result};
+use async_trait::async_trait;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// POSTs offers to a signaling server, the native counterpart of `init_peer_connection`
+/// in `cyberdeck-client-web-sys`
+#[derive(Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    url: String,
+    headers: Vec<(String, String)>,
+    timeout: Option<Duration>,
+    max_retries: u32,
+    initial_backoff: Duration,
+}
+
+impl HttpClient {
+    /// `url` of the endpoint, e.g. `http://localhost:3000/connect`
+    pub fn new(url: &str) -> HttpClient {
+        HttpClient {
+            client: reqwest::Client::new(),
+            url: url.to_owned(),
+            headers: Vec::new(),
+            timeout: None,
+            max_retries: 0,
+            initial_backoff: Duration::from_millis(500),
+        }
+    }
+
+    /// Sent with every request, e.g. an `Authorization` header
+    pub fn header(mut self, name: &str, value: &str) -> HttpClient {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Limit for a single request. Without candidates trickled the server answers only once it
+    /// gathered its own, leave room for that.
+    pub fn timeout(mut self, timeout: Duration) -> HttpClient {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Retry requests that did not reach the server or were turned away with
+    /// `503 Service Unavailable` up to `max_retries` times, waiting `initial_backoff` before the
+    /// first retry and twice as long before every next one. Every offer the server takes creates
+    /// a peer, so timeouts and other errors are not retried.
+    pub fn retries(mut self, max_retries: u32, initial_backoff: Duration) -> HttpClient {
+        self.max_retries = max_retries;
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Create an offer on `peer`, send it and apply the answer
+    pub async fn connect(&self, peer: &mut Peer) -> Result<()> {
+        let offer = peer.create_offer().await?;
+        let answer = self.exchange(&offer).await?;
+        peer.receive_answer(&answer).await
+    }
+
+    /// Send an encoded offer and return the server's answer
+    pub async fn exchange(&self, offer: &str) -> Result<String> {
+        let mut backoff = self.initial_backoff;
+        let mut retries_left = self.max_retries;
+        loop {
+            match self.post(offer).await {
+                Err(Attempt::Retry(_)) if retries_left > 0 => {
+                    retries_left -= 1;
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(Attempt::Retry(e)) | Err(Attempt::Fail(e)) => return Err(e),
+                Ok(answer) => return Ok(answer),
+            }
+        }
+    }
+
+    async fn post(&self, offer: &str) -> Result<String, Attempt> {
+        let mut request = self.client.post(&self.url).json(offer);
+        for (name, value) in &self.headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+
+        let response = request.send().await.map_err(|e| {
+            let error = Error::Signaling(e.to_string());
+            // anything after the connection was made may have reached the server
+            if e.is_connect() {
+                Attempt::Retry(error)
+            } else {
+                Attempt::Fail(error)
+            }
+        })?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            let error = Error::Signaling(format!("{}: {}", status, body));
+            return Err(if status == reqwest::StatusCode::SERVICE_UNAVAILABLE {
+                Attempt::Retry(error)
+            } else {
+                Attempt::Fail(error)
+            });
+        }
+        response
+            .json::<String>()
+            .await
+            .map_err(|e| Attempt::Fail(Error::SignalingDecode(e.to_string())))
+    }
+
+    /// Use this client for `Peer::connect_with`
+    pub fn into_signaler(self) -> HttpSignaler {
+        HttpSignaler {
+            client: self,
+            offered: false,
+            answers: VecDeque::new(),
+        }
+    }
+}
+
+enum Attempt {
+    Retry(Error),
+    Fail(Error),
+}
+
+/// `Signaler` that POSTs offers to a signaling server and hands back its answers.
+/// The endpoint answers every offer with a new peer, so there is no renegotiation and
+/// no trickle ICE: use it with the impolite role and trickle ICE off. Offers after the first
+/// one fail instead of creating another peer.
+pub struct HttpSignaler {
+    client: HttpClient,
+    offered: bool,
+    answers: VecDeque<Description>,
+}
+
+impl HttpSignaler {
+    /// `url` of the endpoint, e.g. `http://localhost:3000/connect`
+    pub fn new(url: &str) -> HttpSignaler {
+        HttpClient::new(url).into_signaler()
+    }
+}
+
+#[async_trait]
+impl Signaler for HttpSignaler {
+    async fn send_description(&mut self, description: Description) -> Result<()> {
+        let offer = match description {
+            Description::Offer(offer) => offer,
+            Description::Answer(_) => {
+                return Err(Error::Signaling(
+                    "the HTTP endpoint only accepts offers".to_owned(),
+                ))
+            }
+        };
+        if self.offered {
+            return Err(Error::Signaling(
+                "the HTTP endpoint creates a new peer for every offer, it cannot renegotiate"
+                    .to_owned(),
+            ));
+        }
+        self.offered = true;
+        let answer = self.client.exchange(&offer).await?;
+        self.answers.push_back(Description::Answer(answer));
+        Ok(())
+    }
+
+    async fn send_candidate(&mut self, _candidate: String) -> Result<()> {
+        Err(Error::Signaling(
+            "the HTTP endpoint does not take trickled candidates".to_owned(),
+        ))
+    }
+
+    async fn receive(&mut self) -> Result<Option<Signal>> {
+        match self.answers.pop_front() {
+            Some(answer) => Ok(Some(Signal::Description(answer))),
+            // answers only come back from our own requests
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Answers every offer with `status` on a free local port, returns the url and the number
+    /// of offers received
+    fn start_server(status: StatusCode) -> (String, Arc<AtomicUsize>) {
+        let offers = Arc::new(AtomicUsize::new(0));
+        let counted = offers.clone();
+        let app = Router::new().route(
+            "/connect",
+            post(move || {
+                counted.fetch_add(1, Ordering::SeqCst);
+                async move { (status, Json("answer")) }
+            }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/connect", listener.local_addr().unwrap());
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+        (url, offers)
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_not_retried() {
+        let (url, offers) = start_server(StatusCode::INTERNAL_SERVER_ERROR);
+        let client = HttpClient::new(&url).retries(3, Duration::from_millis(1));
+        assert!(client.exchange("offer").await.is_err());
+        assert_eq!(offers.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn unavailable_server_is_retried() {
+        let (url, offers) = start_server(StatusCode::SERVICE_UNAVAILABLE);
+        let client = HttpClient::new(&url).retries(3, Duration::from_millis(1));
+        assert!(client.exchange("offer").await.is_err());
+        assert_eq!(offers.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn signaler_sends_only_the_first_offer() {
+        let (url, offers) = start_server(StatusCode::OK);
+        let mut signaler = HttpSignaler::new(&url);
+        signaler
+            .send_description(Description::Offer("first".to_owned()))
+            .await
+            .unwrap();
+        let renegotiation = signaler
+            .send_description(Description::Offer("again".to_owned()))
+            .await;
+        assert!(renegotiation.is_err());
+        assert_eq!(offers.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            signaler.receive().await.unwrap(),
+            Some(Signal::Description(Description::Answer(
+                "answer".to_owned()
+            )))
+        );
+    }
+}
@@ -0,0 +1,16 @@
+//! Helpers for exchanging offers and answers between peers
+
+#[cfg(feature = "http-client")]
+pub mod http_client;
+mod protocol;
+mod signaler;
+#[cfg(feature = "axum")]
+mod relay;
+#[cfg(feature = "axum")]
+pub mod server;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+pub use protocol::SignalMessage;
+pub(crate) use signaler::{run_signaler, OutgoingSignals};
+pub use signaler::{Description, InMemorySignaler, Signal, Signaler, StdioSignaler};
@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+
+/// Messages of the WebSocket signaling protocol, sent as JSON text frames tagged by `type`.
+///
+/// Clients `join` a room and get the ids of the peers already in it. `offer`, `answer` and `candidate`
+/// are relayed to the room member named in `peer`, the server replaces it with the sender's id.
+/// Without `peer` they go to a peer hosted by the server itself. Descriptions and candidates are
+/// encoded like everywhere else, with the peer's `SdpCodec`. Offers on an established connection
+/// renegotiate it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SignalMessage {
+    Join {
+        room: String,
+    },
+    /// Reply to `Join` with this connection's id and the other members of the room
+    Joined {
+        id: String,
+        peers: Vec<String>,
+    },
+    PeerJoined {
+        id: String,
+    },
+    PeerLeft {
+        id: String,
+    },
+    Offer {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        peer: Option<String>,
+        sdp: String,
+    },
+    Answer {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        peer: Option<String>,
+        sdp: String,
+    },
+    Candidate {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        peer: Option<String>,
+        candidate: String,
+    },
+    Error {
+        message: String,
+    },
+}
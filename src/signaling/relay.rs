@@ -0,0 +1,282 @@
+use super::server::HostedPeers;
+use super::SignalMessage;
+use crate::{dispatch, Configuration, Error, Peer, PeerEvent, Result};
+use axum::extract::ws::{Message, WebSocket};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+type BoxedEventFuture = Pin<Box<dyn Future<Output = ()> + Send + Sync>>;
+
+/// Members of every room by connection id, rooms are removed with their last member
+#[derive(Default)]
+pub(crate) struct Rooms {
+    rooms: Mutex<HashMap<String, HashMap<String, UnboundedSender<SignalMessage>>>>,
+}
+
+impl Rooms {
+    /// Returns the ids of the members that were already in the room
+    fn join(&self, room: &str, id: &str, sender: UnboundedSender<SignalMessage>) -> Vec<String> {
+        let mut rooms = self.rooms.lock().unwrap();
+        let members = rooms.entry(room.to_owned()).or_default();
+        let peers = members.keys().cloned().collect();
+        for member in members.values() {
+            let _ = member.send(SignalMessage::PeerJoined { id: id.to_owned() });
+        }
+        members.insert(id.to_owned(), sender);
+        peers
+    }
+
+    fn leave(&self, room: &str, id: &str) {
+        let mut rooms = self.rooms.lock().unwrap();
+        if let Some(members) = rooms.get_mut(room) {
+            members.remove(id);
+            for member in members.values() {
+                let _ = member.send(SignalMessage::PeerLeft { id: id.to_owned() });
+            }
+            if members.is_empty() {
+                rooms.remove(room);
+            }
+        }
+    }
+
+    /// Deliver `message` to another member of `room`, false if there is no such member
+    fn relay(&self, room: &str, to: &str, message: SignalMessage) -> bool {
+        let rooms = self.rooms.lock().unwrap();
+        match rooms.get(room).and_then(|members| members.get(to)) {
+            Some(member) => member.send(message).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// State of a single WebSocket connection
+struct Connection<H> {
+    id: String,
+    room: Option<String>,
+    rooms: Arc<Rooms>,
+    outgoing: UnboundedSender<SignalMessage>,
+    // shared with the task dispatching its events, which keeps it alive after the socket is gone
+    server_peer: Option<Arc<tokio::sync::Mutex<Peer>>>,
+    handle_message: Arc<H>,
+    config: Configuration,
+    hosted: Arc<HostedPeers>,
+}
+
+pub(crate) async fn handle_socket<H, T>(
+    mut socket: WebSocket,
+    rooms: Arc<Rooms>,
+    handle_message: Arc<H>,
+    config: Configuration,
+    hosted: Arc<HostedPeers>,
+) where
+    H: Fn(u128, PeerEvent) -> T + Send + Sync + 'static,
+    T: Future<Output = ()> + Send + Sync + 'static,
+{
+    let (outgoing, mut outgoing_rx) = mpsc::unbounded_channel();
+    let mut connection = Connection {
+        id: uuid::Uuid::new_v4().to_string(),
+        room: None,
+        rooms,
+        outgoing,
+        server_peer: None,
+        handle_message,
+        config,
+        hosted,
+    };
+
+    loop {
+        tokio::select! {
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = match serde_json::from_str::<SignalMessage>(&text) {
+                        Ok(message) => connection.handle(message).await,
+                        Err(e) => Some(SignalMessage::Error { message: e.to_string() }),
+                    };
+                    if let Some(reply) = reply {
+                        let _ = connection.outgoing.send(reply);
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            Some(message) = outgoing_rx.recv() => {
+                let text = serde_json::to_string(&message).expect("signal messages serialize");
+                if socket.send(Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    connection.leave();
+}
+
+impl<H, T> Connection<H>
+where
+    H: Fn(u128, PeerEvent) -> T + Send + Sync + 'static,
+    T: Future<Output = ()> + Send + Sync + 'static,
+{
+    /// Handle a message from the client, returning the reply to send back
+    async fn handle(&mut self, message: SignalMessage) -> Option<SignalMessage> {
+        match message {
+            SignalMessage::Join { room } => {
+                self.leave();
+                let peers = self.rooms.join(&room, &self.id, self.outgoing.clone());
+                self.room = Some(room);
+                Some(SignalMessage::Joined {
+                    id: self.id.clone(),
+                    peers,
+                })
+            }
+            SignalMessage::Offer { peer: Some(to), sdp } => self.relay(
+                &to,
+                SignalMessage::Offer {
+                    peer: Some(self.id.clone()),
+                    sdp,
+                },
+            ),
+            SignalMessage::Answer { peer: Some(to), sdp } => self.relay(
+                &to,
+                SignalMessage::Answer {
+                    peer: Some(self.id.clone()),
+                    sdp,
+                },
+            ),
+            SignalMessage::Candidate {
+                peer: Some(to),
+                candidate,
+            } => self.relay(
+                &to,
+                SignalMessage::Candidate {
+                    peer: Some(self.id.clone()),
+                    candidate,
+                },
+            ),
+            SignalMessage::Offer { peer: None, sdp } => match self.receive_offer(&sdp).await {
+                Ok(answer) => Some(SignalMessage::Answer {
+                    peer: None,
+                    sdp: answer,
+                }),
+                Err(e) => Some(SignalMessage::Error {
+                    message: e.to_string(),
+                }),
+            },
+            SignalMessage::Answer { peer: None, sdp } => {
+                let result = match &self.server_peer {
+                    Some(peer) => peer.lock().await.receive_answer(&sdp).await,
+                    None => return Some(no_server_peer()),
+                };
+                result.err().map(|e| SignalMessage::Error {
+                    message: e.to_string(),
+                })
+            }
+            SignalMessage::Candidate {
+                peer: None,
+                candidate,
+            } => {
+                let result = match &self.server_peer {
+                    Some(peer) => peer.lock().await.add_ice_candidate(&candidate).await,
+                    None => return Some(no_server_peer()),
+                };
+                result.err().map(|e| SignalMessage::Error {
+                    message: e.to_string(),
+                })
+            }
+            SignalMessage::Joined { .. }
+            | SignalMessage::PeerJoined { .. }
+            | SignalMessage::PeerLeft { .. }
+            | SignalMessage::Error { .. } => Some(SignalMessage::Error {
+                message: "only the server sends this message".to_owned(),
+            }),
+        }
+    }
+
+    fn relay(&self, to: &str, message: SignalMessage) -> Option<SignalMessage> {
+        let delivered = match &self.room {
+            Some(room) => self.rooms.relay(room, to, message),
+            None => false,
+        };
+        if delivered {
+            None
+        } else {
+            Some(SignalMessage::Error {
+                message: format!("no peer {} in this room", to),
+            })
+        }
+    }
+
+    /// Answer an offer for the peer hosted by the server, creating it on the first offer
+    async fn receive_offer(&mut self, offer: &str) -> Result<String> {
+        if self.server_peer.is_none() {
+            self.server_peer = Some(self.start_server_peer().await?);
+        }
+        let peer = self.server_peer.as_ref().unwrap();
+        let answer = peer.lock().await.receive_offer(offer).await;
+        answer
+    }
+
+    async fn start_server_peer(&self) -> Result<Arc<tokio::sync::Mutex<Peer>>> {
+        if self.hosted.is_shutting_down() {
+            return Err(Error::ShuttingDown);
+        }
+        let mut config = self.config.clone();
+        config.trickle_ice = true;
+        let dispatch_mode = config.dispatch_mode;
+        let channel_queue_capacity = config.event_queue_capacity;
+        let (peer, events) = Peer::new_with_stream(config).await?;
+        let peer_id = peer.peer_id;
+        let peer = Arc::new(tokio::sync::Mutex::new(peer));
+        if let Err(e) = self.hosted.insert(peer_id, peer.clone()) {
+            let _ = peer.lock().await.close().await;
+            return Err(e);
+        }
+
+        // candidates go back over the socket, everything else to the user's handler
+        let handle_message = self.handle_message.clone();
+        let outgoing = self.outgoing.clone();
+        let handle_event = move |peer_id, e| -> BoxedEventFuture {
+            match e {
+                PeerEvent::IceCandidate(candidate) => {
+                    let _ = outgoing.send(SignalMessage::Candidate {
+                        peer: None,
+                        candidate,
+                    });
+                    Box::pin(async {})
+                }
+                e => Box::pin(handle_message(peer_id, e)),
+            }
+        };
+
+        let keep_alive = peer.clone();
+        let hosted = self.hosted.clone();
+        tokio::spawn(async move {
+            dispatch::dispatch(
+                dispatch_mode,
+                peer_id,
+                events,
+                handle_event,
+                channel_queue_capacity,
+            )
+            .await;
+            hosted.remove(peer_id);
+            drop(keep_alive);
+        });
+
+        Ok(peer)
+    }
+
+    fn leave(&mut self) {
+        if let Some(room) = self.room.take() {
+            self.rooms.leave(&room, &self.id);
+        }
+    }
+}
+
+fn no_server_peer() -> SignalMessage {
+    SignalMessage::Error {
+        message: "no offer was sent to the server".to_owned(),
+    }
+}
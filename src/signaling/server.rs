@@ -0,0 +1,247 @@
+//! Ready-made axum signaling server.
+//!
+//! Clients POST their offer as a JSON string to `/connect` and get the answer back the same way,
+//! which is what `cyberdeck-client-web-sys` and `examples/receiver.rs` speak. Every peer is kept
+//! alive until it is closed or its connection fails, there is no need to hold on to it.
+//!
+//! `/ws` speaks the WebSocket protocol of `SignalMessage`: offers to the server are answered by a
+//! peer like those of `/connect` but with trickle ICE, and clients in the same room can connect
+//! to each other with the server relaying their messages.
+//!
+//! `SignalingServer::shutdown_handle` closes all these peers when the process stops.
+
+use super::relay::{self, Rooms};
+use crate::shutdown::{self, ShutdownOptions, ShutdownReport};
+use crate::{dispatch, Configuration, Error, Peer, PeerEvent};
+use axum::extract::ws::WebSocketUpgrade;
+use axum::http::{header, Method, StatusCode};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+pub use tower_http::cors::{Any, CorsLayer};
+
+/// Builds the `Router` of a signaling server
+pub struct SignalingServer<H> {
+    handle_message: H,
+    config: Configuration,
+    cors: CorsLayer,
+    path: String,
+    websocket_path: String,
+    hosted: Arc<HostedPeers>,
+}
+
+/// Closes the peers of a `SignalingServer`, see `SignalingServer::shutdown_handle`
+#[derive(Clone)]
+pub struct ServerShutdown {
+    hosted: Arc<HostedPeers>,
+}
+
+impl ServerShutdown {
+    /// Answer new offers with `503 Service Unavailable` and close every peer of the server
+    /// gracefully, at the same time. The HTTP server itself is stopped with
+    /// `axum::Server::with_graceful_shutdown`.
+    pub async fn shutdown(&self, options: &ShutdownOptions) -> ShutdownReport {
+        let peers = {
+            let mut peers = self.hosted.peers.lock().unwrap();
+            self.hosted.shutting_down.store(true, Ordering::SeqCst);
+            peers.drain().map(|(_, peer)| peer).collect()
+        };
+        shutdown::shutdown_peers(peers, options).await
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.hosted.is_shutting_down()
+    }
+}
+
+/// Peers created by the server, kept until their events end to close them on shutdown
+#[derive(Default)]
+pub(crate) struct HostedPeers {
+    peers: Mutex<HashMap<u128, Arc<tokio::sync::Mutex<Peer>>>>,
+    shutting_down: AtomicBool,
+}
+
+impl HostedPeers {
+    pub(crate) fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Fails once the shutdown started, the caller then closes the peer
+    pub(crate) fn insert(
+        &self,
+        peer_id: u128,
+        peer: Arc<tokio::sync::Mutex<Peer>>,
+    ) -> Result<(), Error> {
+        let mut peers = self.peers.lock().unwrap();
+        if self.is_shutting_down() {
+            return Err(Error::ShuttingDown);
+        }
+        peers.insert(peer_id, peer);
+        Ok(())
+    }
+
+    pub(crate) fn remove(&self, peer_id: u128) {
+        self.peers.lock().unwrap().remove(&peer_id);
+    }
+}
+
+/// Router answering offers on `/connect` and `/ws` with the default configuration and CORS
+pub fn router<H, T>(handle_message: H) -> Router
+where
+    H: Fn(u128, PeerEvent) -> T + Send + Sync + 'static,
+    T: Future<Output = ()> + Send + Sync + 'static,
+{
+    SignalingServer::new(handle_message).router()
+}
+
+impl<H, T> SignalingServer<H>
+where
+    H: Fn(u128, PeerEvent) -> T + Send + Sync + 'static,
+    T: Future<Output = ()> + Send + Sync + 'static,
+{
+    /// `handle_message` receives the events of every connected peer
+    pub fn new(handle_message: H) -> SignalingServer<H> {
+        SignalingServer {
+            handle_message,
+            config: Configuration::default(),
+            cors: default_cors(),
+            path: "/connect".to_owned(),
+            websocket_path: "/ws".to_owned(),
+            hosted: Arc::new(HostedPeers::default()),
+        }
+    }
+
+    /// Configuration of the peers created for incoming offers.
+    /// Candidates are sent along with the answer, trickle ICE is not used.
+    pub fn configuration(mut self, config: Configuration) -> SignalingServer<H> {
+        self.config = config;
+        self
+    }
+
+    /// Replaces the default CORS layer, which allows `GET` and `POST` with JSON from any origin
+    pub fn cors(mut self, cors: CorsLayer) -> SignalingServer<H> {
+        self.cors = cors;
+        self
+    }
+
+    /// Route of the offer endpoint, `/connect` by default
+    pub fn path(mut self, path: &str) -> SignalingServer<H> {
+        self.path = path.to_owned();
+        self
+    }
+
+    /// Route of the WebSocket endpoint, `/ws` by default
+    pub fn websocket_path(mut self, path: &str) -> SignalingServer<H> {
+        self.websocket_path = path.to_owned();
+        self
+    }
+
+    /// For closing the peers of the router when the process stops, take it before `router`
+    pub fn shutdown_handle(&self) -> ServerShutdown {
+        ServerShutdown {
+            hosted: self.hosted.clone(),
+        }
+    }
+
+    pub fn router(self) -> Router {
+        let handle_message = Arc::new(self.handle_message);
+        let rooms = Arc::new(Rooms::default());
+
+        let websocket = {
+            let handle_message = handle_message.clone();
+            let config = self.config.clone();
+            let hosted = self.hosted.clone();
+            move |upgrade: WebSocketUpgrade| {
+                let rooms = rooms.clone();
+                let handle_message = handle_message.clone();
+                let config = config.clone();
+                let hosted = hosted.clone();
+                async move {
+                    upgrade.on_upgrade(move |socket| {
+                        relay::handle_socket(socket, rooms, handle_message, config, hosted)
+                    })
+                }
+            }
+        };
+
+        let mut config = self.config;
+        config.trickle_ice = false;
+        let hosted = self.hosted;
+        let connect = move |Json(offer): Json<String>| {
+            let handle_message = handle_message.clone();
+            let config = config.clone();
+            let hosted = hosted.clone();
+            async move {
+                match start_peer_connection(offer, config, handle_message, hosted).await {
+                    Ok(answer) => Ok(Json(answer)),
+                    Err(e) => Err((status_code(&e), e.to_string())),
+                }
+            }
+        };
+
+        Router::new()
+            .route(&self.path, post(connect))
+            .route(&self.websocket_path, get(websocket))
+            .layer(self.cors)
+    }
+}
+
+fn default_cors() -> CorsLayer {
+    CorsLayer::new()
+        .allow_methods([Method::GET, Method::POST])
+        .allow_origin(Any)
+        .allow_headers([header::CONTENT_TYPE])
+}
+
+fn status_code(e: &Error) -> StatusCode {
+    match e {
+        Error::SignalingDecode(_) | Error::Sdp(_) => StatusCode::BAD_REQUEST,
+        Error::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+async fn start_peer_connection<H, T>(
+    offer: String,
+    config: Configuration,
+    handle_message: Arc<H>,
+    hosted: Arc<HostedPeers>,
+) -> Result<String, Error>
+where
+    H: Fn(u128, PeerEvent) -> T + Send + Sync + 'static,
+    T: Future<Output = ()> + Send + Sync + 'static,
+{
+    if hosted.is_shutting_down() {
+        return Err(Error::ShuttingDown);
+    }
+    let dispatch_mode = config.dispatch_mode;
+    let channel_queue_capacity = config.event_queue_capacity;
+    let (mut peer, events) = Peer::new_with_stream(config).await?;
+    let answer = peer.receive_offer(&offer).await?;
+
+    let peer_id = peer.peer_id;
+    let peer = Arc::new(tokio::sync::Mutex::new(peer));
+    if let Err(e) = hosted.insert(peer_id, peer.clone()) {
+        let _ = peer.lock().await.close().await;
+        return Err(e);
+    }
+
+    // the event stream ends once the connection failed or was closed, until then the peer lives here
+    tokio::spawn(async move {
+        dispatch::dispatch(
+            dispatch_mode,
+            peer_id,
+            events,
+            move |peer_id, e| handle_message(peer_id, e),
+            channel_queue_capacity,
+        )
+        .await;
+        hosted.remove(peer_id);
+        drop(peer);
+    });
+
+    Ok(answer)
+}
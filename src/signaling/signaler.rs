@@ -0,0 +1,328 @@
+use crate::negotiation::Negotiator;
+use crate::queue::EventSender;
+use crate::{Error, PeerEvent, Result, SdpCodec};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex, Weak};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines, Stdin, Stdout};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+
+/// An encoded session description, in the format of the peer's `SdpCodec`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Description {
+    Offer(String),
+    Answer(String),
+}
+
+impl Description {
+    pub fn sdp(&self) -> &str {
+        match self {
+            Description::Offer(sdp) | Description::Answer(sdp) => sdp,
+        }
+    }
+
+    fn decode(sdp_codec: &dyn SdpCodec, encoded: String) -> Result<Description> {
+        let desc_data = sdp_codec.decode(&encoded)?;
+        let desc = serde_json::from_str::<RTCSessionDescription>(&desc_data)?;
+        match desc.sdp_type {
+            RTCSdpType::Offer => Ok(Description::Offer(encoded)),
+            RTCSdpType::Answer => Ok(Description::Answer(encoded)),
+            sdp_type => Err(Error::SignalingDecode(format!(
+                "unexpected description type {}",
+                sdp_type
+            ))),
+        }
+    }
+}
+
+/// A message from the other side
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Signal {
+    Description(Description),
+    Candidate(String),
+    /// A problem the signaling channel reports without breaking, e.g. a relay server rejecting a
+    /// message. Delivered as `PeerEvent::Error` while signaling goes on.
+    Error(String),
+}
+
+/// Carries offers, answers and candidates between two peers, see `Peer::connect_with`
+#[async_trait]
+pub trait Signaler: Send {
+    async fn send_description(&mut self, description: Description) -> Result<()>;
+
+    /// Only used in trickle ICE mode
+    async fn send_candidate(&mut self, candidate: String) -> Result<()>;
+
+    /// Next message from the other side, `None` once signaling is over.
+    /// An error ends signaling as well, so problems that leave the channel usable should be
+    /// returned as `Signal::Error`.
+    /// Must be cancel safe, it is raced against outgoing messages.
+    async fn receive(&mut self) -> Result<Option<Signal>>;
+}
+
+/// Two ends of a signaling channel inside one process, e.g. to connect two peers in a test
+pub struct InMemorySignaler {
+    outgoing: UnboundedSender<Signal>,
+    incoming: UnboundedReceiver<Signal>,
+}
+
+impl InMemorySignaler {
+    pub fn pair() -> (InMemorySignaler, InMemorySignaler) {
+        let (a_tx, a_rx) = mpsc::unbounded_channel();
+        let (b_tx, b_rx) = mpsc::unbounded_channel();
+        (
+            InMemorySignaler {
+                outgoing: a_tx,
+                incoming: b_rx,
+            },
+            InMemorySignaler {
+                outgoing: b_tx,
+                incoming: a_rx,
+            },
+        )
+    }
+
+    fn send(&self, signal: Signal) -> Result<()> {
+        self.outgoing
+            .send(signal)
+            .map_err(|_| Error::Signaling("the other side is gone".to_owned()))
+    }
+}
+
+#[async_trait]
+impl Signaler for InMemorySignaler {
+    async fn send_description(&mut self, description: Description) -> Result<()> {
+        self.send(Signal::Description(description))
+    }
+
+    async fn send_candidate(&mut self, candidate: String) -> Result<()> {
+        self.send(Signal::Candidate(candidate))
+    }
+
+    async fn receive(&mut self) -> Result<Option<Signal>> {
+        Ok(self.incoming.recv().await)
+    }
+}
+
+/// Prints one message per line to stdout and reads the other side's from stdin,
+/// as `offer <sdp>`, `answer <sdp>` or `candidate <candidate>`. For copy and paste between terminals.
+pub struct StdioSignaler {
+    stdin: Lines<BufReader<Stdin>>,
+    stdout: Stdout,
+}
+
+impl StdioSignaler {
+    pub fn new() -> StdioSignaler {
+        StdioSignaler {
+            stdin: BufReader::new(tokio::io::stdin()).lines(),
+            stdout: tokio::io::stdout(),
+        }
+    }
+
+    async fn write_line(&mut self, kind: &str, value: &str) -> Result<()> {
+        let line = format!("{} {}\n", kind, value);
+        self.stdout
+            .write_all(line.as_bytes())
+            .await
+            .map_err(|e| Error::Signaling(e.to_string()))?;
+        self.stdout
+            .flush()
+            .await
+            .map_err(|e| Error::Signaling(e.to_string()))
+    }
+}
+
+impl Default for StdioSignaler {
+    fn default() -> Self {
+        StdioSignaler::new()
+    }
+}
+
+#[async_trait]
+impl Signaler for StdioSignaler {
+    async fn send_description(&mut self, description: Description) -> Result<()> {
+        match description {
+            Description::Offer(sdp) => self.write_line("offer", &sdp).await,
+            Description::Answer(sdp) => self.write_line("answer", &sdp).await,
+        }
+    }
+
+    async fn send_candidate(&mut self, candidate: String) -> Result<()> {
+        self.write_line("candidate", &candidate).await
+    }
+
+    async fn receive(&mut self) -> Result<Option<Signal>> {
+        loop {
+            let line = match self.stdin.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => return Ok(None),
+                Err(e) => return Err(Error::Signaling(e.to_string())),
+            };
+            let signal = match line.trim().split_once(' ') {
+                Some(("offer", sdp)) => Signal::Description(Description::Offer(sdp.to_owned())),
+                Some(("answer", sdp)) => Signal::Description(Description::Answer(sdp.to_owned())),
+                Some(("candidate", candidate)) => Signal::Candidate(candidate.to_owned()),
+                // blank lines and stray output pasted along
+                _ => continue,
+            };
+            return Ok(Some(signal));
+        }
+    }
+}
+
+/// Outgoing messages of the peer, queued by its negotiation and candidate hooks.
+/// Local candidates wait until a description went out, the other side has no use for them before.
+/// Only the first round of negotiation gathers candidates, later ones reuse them.
+#[derive(Clone)]
+pub(crate) struct OutgoingSignals {
+    signals: UnboundedSender<Signal>,
+    /// `None` once a description was queued
+    held_candidates: Arc<Mutex<Option<Vec<String>>>>,
+}
+
+impl OutgoingSignals {
+    pub(crate) fn new() -> (OutgoingSignals, UnboundedReceiver<Signal>) {
+        let (signals, signals_rx) = mpsc::unbounded_channel();
+        let outgoing = OutgoingSignals {
+            signals,
+            held_candidates: Arc::new(Mutex::new(Some(vec![]))),
+        };
+        (outgoing, signals_rx)
+    }
+
+    /// Queue `description` followed by the candidates held back for it
+    pub(crate) fn queue_description(
+        &self,
+        sdp_codec: &dyn SdpCodec,
+        description: String,
+    ) -> Result<()> {
+        let description = Description::decode(sdp_codec, description)?;
+        let mut held_candidates = self.held_candidates.lock().unwrap();
+        self.send(Signal::Description(description))?;
+        for candidate in held_candidates.take().into_iter().flatten() {
+            self.send(Signal::Candidate(candidate))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn queue_candidate(&self, candidate: String) -> Result<()> {
+        match self.held_candidates.lock().unwrap().as_mut() {
+            Some(held_candidates) => {
+                held_candidates.push(candidate);
+                Ok(())
+            }
+            None => self.send(Signal::Candidate(candidate)),
+        }
+    }
+
+    fn send(&self, signal: Signal) -> Result<()> {
+        self.signals
+            .send(signal)
+            .map_err(|_| Error::Signaling("signaling has stopped".to_owned()))
+    }
+}
+
+/// Pump messages between the peer and the signaler until either side is done
+pub(crate) async fn run_signaler(
+    mut signaler: impl Signaler,
+    mut outgoing: UnboundedReceiver<Signal>,
+    peer_connection: Weak<RTCPeerConnection>,
+    negotiator: Weak<Negotiator>,
+    sdp_codec: Arc<dyn SdpCodec>,
+    events: EventSender,
+) {
+    loop {
+        tokio::select! {
+            signal = outgoing.recv() => {
+                let result = match signal {
+                    Some(Signal::Description(description)) => signaler.send_description(description).await,
+                    Some(Signal::Candidate(candidate)) => signaler.send_candidate(candidate).await,
+                    // only ever received
+                    Some(Signal::Error(_)) => Ok(()),
+                    // the peer connection is gone
+                    None => return,
+                };
+                if let Err(e) = result {
+                    events.send(PeerEvent::Error(e)).await;
+                }
+            }
+            incoming = signaler.receive() => {
+                let signal = match incoming {
+                    Ok(Some(signal)) => signal,
+                    Ok(None) => return,
+                    Err(e) => {
+                        // a broken signaler keeps failing, calling it again would only flood the events
+                        events.send(PeerEvent::Error(e)).await;
+                        return;
+                    }
+                };
+                let (peer_connection, negotiator) = match (peer_connection.upgrade(), negotiator.upgrade()) {
+                    (Some(peer_connection), Some(negotiator)) => (peer_connection, negotiator),
+                    _ => return,
+                };
+                let result = match signal {
+                    Signal::Description(description) => {
+                        negotiator.receive_description(&peer_connection, description.sdp()).await
+                    }
+                    Signal::Candidate(candidate) => {
+                        add_candidate(&peer_connection, &negotiator, sdp_codec.as_ref(), &candidate).await
+                    }
+                    Signal::Error(message) => Err(Error::Signaling(message)),
+                };
+                if let Err(e) = result {
+                    events.send(PeerEvent::Error(e)).await;
+                }
+            }
+        }
+    }
+}
+
+async fn add_candidate(
+    peer_connection: &RTCPeerConnection,
+    negotiator: &Negotiator,
+    sdp_codec: &dyn SdpCodec,
+    candidate: &str,
+) -> Result<()> {
+    let candidate_data = sdp_codec.decode(candidate)?;
+    let candidate = serde_json::from_str::<RTCIceCandidateInit>(&candidate_data)?;
+    match peer_connection.add_ice_candidate(candidate).await {
+        Ok(()) => Ok(()),
+        Err(_) if negotiator.ignoring_offer() => Ok(()),
+        Err(e) => Err(Error::Ice(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Base64Codec;
+
+    fn offer() -> String {
+        let json = serde_json::json!({"type": "offer", "sdp": "v=0\r\n"}).to_string();
+        Base64Codec.encode(&json).unwrap()
+    }
+
+    #[test]
+    fn candidates_wait_for_the_first_description() {
+        let (outgoing, mut signals) = OutgoingSignals::new();
+        outgoing.queue_candidate("first".to_owned()).unwrap();
+        outgoing.queue_candidate("second".to_owned()).unwrap();
+        assert!(signals.try_recv().is_err());
+
+        outgoing.queue_description(&Base64Codec, offer()).unwrap();
+        outgoing.queue_candidate("third".to_owned()).unwrap();
+        let received: Vec<Signal> = std::iter::from_fn(|| signals.try_recv().ok()).collect();
+        assert_eq!(
+            received,
+            [
+                Signal::Description(Description::Offer(offer())),
+                Signal::Candidate("first".to_owned()),
+                Signal::Candidate("second".to_owned()),
+                Signal::Candidate("third".to_owned()),
+            ]
+        );
+    }
+}
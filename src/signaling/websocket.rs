@@ -0,0 +1,136 @@
+//! Client of the WebSocket signaling protocol served by `signaling::server`, see `SignalMessage`.
+
+use super::{Description, Signal, SignalMessage, Signaler};
+use crate::{Error, Result};
+use async_trait::async_trait;
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+pub struct WebSocketSignaling {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    id: Option<String>,
+}
+
+impl WebSocketSignaling {
+    /// Connect to a signaling server, e.g. `ws://localhost:3000/ws`
+    pub async fn connect(url: &str) -> Result<WebSocketSignaling> {
+        let (socket, _) = connect_async(url)
+            .await
+            .map_err(|e| Error::Signaling(e.to_string()))?;
+        Ok(WebSocketSignaling { socket, id: None })
+    }
+
+    /// Enter `room`, returning the ids of the peers already in it.
+    /// Messages that arrive before the server confirms are dropped.
+    pub async fn join(&mut self, room: &str) -> Result<Vec<String>> {
+        self.send(&SignalMessage::Join {
+            room: room.to_owned(),
+        })
+        .await?;
+        loop {
+            match self.recv().await? {
+                Some(SignalMessage::Joined { id, peers }) => {
+                    self.id = Some(id);
+                    return Ok(peers);
+                }
+                Some(SignalMessage::Error { message }) => return Err(Error::Signaling(message)),
+                Some(_) => {}
+                None => return Err(Error::Signaling("connection closed".to_owned())),
+            }
+        }
+    }
+
+    /// Id the server assigned to this connection, known once a room was joined
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    pub async fn send(&mut self, message: &SignalMessage) -> Result<()> {
+        let text = serde_json::to_string(message)
+            .map_err(|e| Error::SignalingEncode(e.to_string()))?;
+        self.socket
+            .send(Message::Text(text))
+            .await
+            .map_err(|e| Error::Signaling(e.to_string()))
+    }
+
+    /// Next message from the server, `None` once the connection is closed
+    pub async fn recv(&mut self) -> Result<Option<SignalMessage>> {
+        while let Some(message) = self.socket.next().await {
+            match message.map_err(|e| Error::Signaling(e.to_string()))? {
+                Message::Text(text) => return Ok(Some(serde_json::from_str(&text)?)),
+                Message::Close(_) => return Ok(None),
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// Use this connection for `Peer::connect_with`, talking to the room member `peer`
+    /// or to the server's own peer if `None`
+    pub fn into_signaler(self, peer: Option<String>) -> WebSocketSignaler {
+        WebSocketSignaler {
+            signaling: self,
+            peer,
+        }
+    }
+
+    pub async fn close(&mut self) -> Result<()> {
+        self.socket
+            .close(None)
+            .await
+            .map_err(|e| Error::Signaling(e.to_string()))
+    }
+}
+
+/// `Signaler` over a `WebSocketSignaling` connection, ends when the other peer leaves the room.
+/// Errors reported by the server are passed on as `Signal::Error`.
+pub struct WebSocketSignaler {
+    signaling: WebSocketSignaling,
+    peer: Option<String>,
+}
+
+#[async_trait]
+impl Signaler for WebSocketSignaler {
+    async fn send_description(&mut self, description: Description) -> Result<()> {
+        let peer = self.peer.clone();
+        let message = match description {
+            Description::Offer(sdp) => SignalMessage::Offer { peer, sdp },
+            Description::Answer(sdp) => SignalMessage::Answer { peer, sdp },
+        };
+        self.signaling.send(&message).await
+    }
+
+    async fn send_candidate(&mut self, candidate: String) -> Result<()> {
+        let peer = self.peer.clone();
+        self.signaling
+            .send(&SignalMessage::Candidate { peer, candidate })
+            .await
+    }
+
+    async fn receive(&mut self) -> Result<Option<Signal>> {
+        loop {
+            let signal = match self.signaling.recv().await? {
+                Some(SignalMessage::Offer { peer, sdp }) if peer == self.peer => {
+                    Signal::Description(Description::Offer(sdp))
+                }
+                Some(SignalMessage::Answer { peer, sdp }) if peer == self.peer => {
+                    Signal::Description(Description::Answer(sdp))
+                }
+                Some(SignalMessage::Candidate { peer, candidate }) if peer == self.peer => {
+                    Signal::Candidate(candidate)
+                }
+                Some(SignalMessage::PeerLeft { id }) if Some(&id) == self.peer.as_ref() => {
+                    return Ok(None)
+                }
+                // e.g. a message for a peer that just left, the connection is still fine
+                Some(SignalMessage::Error { message }) => Signal::Error(message),
+                Some(_) => continue,
+                None => return Ok(None),
+            };
+            return Ok(Some(signal));
+        }
+    }
+}
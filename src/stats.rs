@@ -0,0 +1,143 @@
+use crate::queue::EventSender;
+use crate::PeerEvent;
+use std::sync::Weak;
+use std::time::Duration;
+pub use webrtc::data_channel::data_channel_state::RTCDataChannelState;
+pub use webrtc::ice::candidate::{CandidatePairState, CandidateType};
+use webrtc::peer_connection::RTCPeerConnection;
+pub use webrtc::sctp_transport::sctp_transport_state::RTCSctpTransportState;
+use webrtc::stats::{ICECandidateStats, StatsReportType};
+
+/// Snapshot of a peer's connection, see `Peer::stats`
+#[derive(Debug, Clone)]
+pub struct PeerStats {
+    /// The candidate pair ICE settled on, none while not connected
+    pub selected_candidate_pair: Option<CandidatePairStats>,
+    pub data_channels: Vec<DataChannelStats>,
+    pub sctp_transport_state: RTCSctpTransportState,
+}
+
+#[derive(Debug, Clone)]
+pub struct CandidatePairStats {
+    pub local: CandidateStats,
+    pub remote: CandidateStats,
+    pub state: CandidatePairState,
+    /// Latest STUN round trip, none until the ICE agent has measured one
+    pub round_trip_time: Option<Duration>,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl CandidatePairStats {
+    /// Whether traffic goes through a TURN server
+    pub fn is_relayed(&self) -> bool {
+        self.local.candidate_type == CandidateType::Relay
+            || self.remote.candidate_type == CandidateType::Relay
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CandidateStats {
+    pub candidate_type: CandidateType,
+    pub ip: String,
+    pub port: u16,
+    /// Protocol used to reach the TURN server of a relay candidate
+    pub relay_protocol: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct DataChannelStats {
+    pub label: String,
+    pub id: u16,
+    pub state: RTCDataChannelState,
+    pub bytes_sent: usize,
+    pub bytes_received: usize,
+    pub messages_sent: usize,
+    pub messages_received: usize,
+}
+
+impl From<&ICECandidateStats> for CandidateStats {
+    fn from(stats: &ICECandidateStats) -> Self {
+        CandidateStats {
+            candidate_type: stats.candidate_type,
+            ip: stats.ip.clone(),
+            port: stats.port,
+            relay_protocol: stats.relay_protocol.clone(),
+        }
+    }
+}
+
+pub(crate) async fn collect_stats(peer_connection: &RTCPeerConnection) -> PeerStats {
+    let report = peer_connection.get_stats().await;
+
+    let candidate = |id: &str| match report.reports.get(id) {
+        Some(StatsReportType::LocalCandidate(stats))
+        | Some(StatsReportType::RemoteCandidate(stats)) => Some(CandidateStats::from(stats)),
+        _ => None,
+    };
+
+    let mut selected_candidate_pair = None;
+    let mut data_channels = Vec::new();
+    for stats in report.reports.values() {
+        match stats {
+            StatsReportType::CandidatePair(pair)
+                if pair.nominated && pair.state == CandidatePairState::Succeeded =>
+            {
+                if let (Some(local), Some(remote)) = (
+                    candidate(&pair.local_candidate_id),
+                    candidate(&pair.remote_candidate_id),
+                ) {
+                    selected_candidate_pair = Some(CandidatePairStats {
+                        local,
+                        remote,
+                        state: pair.state,
+                        round_trip_time: Some(pair.current_round_trip_time)
+                            .filter(|rtt| *rtt > 0.0)
+                            .map(Duration::from_secs_f64),
+                        bytes_sent: pair.bytes_sent,
+                        bytes_received: pair.bytes_received,
+                    });
+                }
+            }
+            StatsReportType::DataChannel(channel) => data_channels.push(DataChannelStats {
+                label: channel.label.clone(),
+                id: channel.data_channel_identifier,
+                state: channel.state,
+                bytes_sent: channel.bytes_sent,
+                bytes_received: channel.bytes_received,
+                messages_sent: channel.messages_sent,
+                messages_received: channel.messages_received,
+            }),
+            _ => {}
+        }
+    }
+    data_channels.sort_by_key(|c| c.id);
+
+    PeerStats {
+        selected_candidate_pair,
+        data_channels,
+        sctp_transport_state: peer_connection.sctp().state(),
+    }
+}
+
+/// Emit `PeerEvent::Stats` every `interval` until the peer is gone
+pub(crate) async fn report_stats(
+    peer_connection: Weak<RTCPeerConnection>,
+    events: EventSender,
+    interval: Duration,
+) {
+    let mut ticks = tokio::time::interval(interval);
+    // the first tick completes immediately, there is nothing to report yet
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        if events.is_closed() {
+            return;
+        }
+        let stats = match peer_connection.upgrade() {
+            Some(peer_connection) => collect_stats(&peer_connection).await,
+            None => return,
+        };
+        events.send(PeerEvent::Stats(stats)).await;
+    }
+}
@@ -0,0 +1,153 @@
+use crate::pacing::SendPacer;
+use crate::DataChannel;
+use bytes::{Buf, Bytes};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc;
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+
+/// Writes are split into messages of at most this size, small enough for any browser
+const MAX_MESSAGE_SIZE: usize = 16 * 1024;
+/// Received messages waiting to be read, the other side is slowed down once it is full
+const READ_QUEUE_MESSAGES: usize = 64;
+
+type IoFuture = Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
+
+/// A data channel as a byte stream, for tunneling protocols such as TLS or anything framed
+/// with `tokio_util::codec`. Message boundaries are not preserved, so both sides should use a
+/// stream on an ordered and reliable channel, which is the default. One of the
+/// [channel wrappers](crate#channel-wrappers), reads end once the channel is closed. Data
+/// channels cannot be half closed, so shutting down waits for buffered data to be sent and
+/// closes the channel in both directions.
+pub struct DataChannelStream {
+    channel: DataChannel,
+    incoming: mpsc::Receiver<Bytes>,
+    /// What is left of the message being read
+    reading: Bytes,
+    pacer: SendPacer,
+    /// A write that was accepted but is waiting for the buffered amount to drop
+    writing: Option<IoFuture>,
+    closing: Option<IoFuture>,
+    closed: bool,
+}
+
+impl DataChannelStream {
+    /// `channel` has to be open already
+    pub async fn new(channel: DataChannel) -> DataChannelStream {
+        let (incoming, incoming_rx) = mpsc::channel(READ_QUEUE_MESSAGES);
+        channel.on_message(Box::new(move |msg: DataChannelMessage| {
+            let incoming = incoming.clone();
+            // the channel stops reading until there is room, which backs up to the sender
+            Box::pin(async move {
+                let _ = incoming.send(msg.data).await;
+            })
+        }));
+
+        DataChannelStream {
+            pacer: SendPacer::new(channel.clone()).await,
+            channel,
+            incoming: incoming_rx,
+            reading: Bytes::new(),
+            writing: None,
+            closing: None,
+            closed: false,
+        }
+    }
+
+    pub fn channel(&self) -> &DataChannel {
+        &self.channel
+    }
+
+    /// Finish the write in flight, if any
+    fn poll_written(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(writing) = &mut self.writing {
+            let written = ready!(writing.as_mut().poll(cx));
+            self.writing = None;
+            written?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for DataChannelStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        // an empty message would read like the end of the stream
+        while self.reading.is_empty() {
+            match ready!(self.incoming.poll_recv(cx)) {
+                Some(data) => self.reading = data,
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let length = self.reading.len().min(buf.remaining());
+        buf.put_slice(&self.reading[..length]);
+        self.reading.advance(length);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for DataChannelStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        ready!(self.poll_written(cx))?;
+        if self.closing.is_some() || self.closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+
+        let length = buf.len().min(MAX_MESSAGE_SIZE);
+        let data = Bytes::copy_from_slice(&buf[..length]);
+        let channel = self.channel.clone();
+        let pacer = self.pacer.clone();
+        let mut writing: IoFuture = Box::pin(async move {
+            pacer.wait_for_room().await;
+            channel.send(&data).await.map_err(to_io_error)?;
+            Ok(())
+        });
+        // the data is ours now, a failure shows up on the next write or flush
+        match writing.as_mut().poll(cx) {
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Ready(Ok(())) => {}
+            Poll::Pending => self.writing = Some(writing),
+        }
+        Poll::Ready(Ok(length))
+    }
+
+    /// Waits until written data is handed to the channel, not until it is sent
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_written(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_written(cx))?;
+        if self.closed {
+            return Poll::Ready(Ok(()));
+        }
+        if self.closing.is_none() {
+            let channel = self.channel.clone();
+            self.closing = Some(Box::pin(async move {
+                crate::shutdown::flush(std::slice::from_ref(&channel)).await;
+                channel.close().await.map_err(to_io_error)
+            }));
+        }
+        let closed = match &mut self.closing {
+            Some(closing) => ready!(closing.as_mut().poll(cx)),
+            None => Ok(()),
+        };
+        self.closing = None;
+        self.closed = true;
+        Poll::Ready(closed)
+    }
+}
+
+fn to_io_error(e: webrtc::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, e)
+}
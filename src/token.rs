@@ -0,0 +1,412 @@
+use crate::{Error, Result, SdpCodec};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::{DecodeError, Engine};
+use std::convert::{TryFrom, TryInto};
+use std::fmt::Write;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+
+const TOKEN_VERSION: u8 = 1;
+const FINGERPRINT_LEN: usize = 32;
+
+const FLAG_ANSWER: u8 = 0b001;
+const SETUP_SHIFT: u8 = 1;
+const SETUP_ACTPASS: u8 = 0;
+const SETUP_ACTIVE: u8 = 1;
+const SETUP_PASSIVE: u8 = 2;
+
+const ADDRESS_IPV4: u8 = 0;
+const ADDRESS_IPV6: u8 = 1;
+const ADDRESS_HOSTNAME: u8 = 2;
+const ADDRESS_MASK: u8 = 0b011;
+const CANDIDATE_SRFLX: u8 = 0b100;
+
+/// Compact "connection token" for pasting offers and answers by hand.
+///
+/// Only what a data-channel-only session needs is kept: the ICE credentials, the DTLS
+/// fingerprint and the UDP host/srflx candidates. The token carries a version byte and a
+/// CRC32 checksum so an incomplete paste is reported as such. Trickled candidates are not
+/// supported, use it without `ConfigurationBuilder::trickle_ice`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokenCodec;
+
+struct Candidate {
+    srflx: bool,
+    priority: u32,
+    address: String,
+    port: u16,
+}
+
+impl SdpCodec for TokenCodec {
+    fn encode(&self, json: &str) -> Result<String> {
+        let desc = serde_json::from_str::<RTCSessionDescription>(json)
+            .map_err(|e| encode_error(&e.to_string()))?;
+
+        let mut flags = 0;
+        match desc.sdp_type {
+            RTCSdpType::Offer => {}
+            RTCSdpType::Answer => flags |= FLAG_ANSWER,
+            t => return Err(encode_error(&format!("unsupported description type {}", t))),
+        }
+
+        let mut mid = None;
+        let mut ufrag = None;
+        let mut pwd = None;
+        let mut fingerprint = None;
+        let mut setup = SETUP_ACTPASS;
+        let mut candidates = Vec::new();
+        for line in desc.sdp.lines() {
+            let line = line.trim();
+            if let Some(v) = line.strip_prefix("a=mid:") {
+                mid.get_or_insert(v);
+            } else if let Some(v) = line.strip_prefix("a=ice-ufrag:") {
+                ufrag.get_or_insert(v);
+            } else if let Some(v) = line.strip_prefix("a=ice-pwd:") {
+                pwd.get_or_insert(v);
+            } else if let Some(v) = line.strip_prefix("a=fingerprint:") {
+                fingerprint.get_or_insert(parse_fingerprint(v)?);
+            } else if let Some(v) = line.strip_prefix("a=setup:") {
+                setup = match v {
+                    "active" => SETUP_ACTIVE,
+                    "passive" => SETUP_PASSIVE,
+                    _ => SETUP_ACTPASS,
+                };
+            } else if let Some(v) = line.strip_prefix("a=candidate:") {
+                if let Some(candidate) = parse_candidate(v) {
+                    candidates.push(candidate);
+                }
+            }
+        }
+        flags |= setup << SETUP_SHIFT;
+
+        let missing = |name: &str| encode_error(&format!("description has no {}", name));
+        let mut b = vec![TOKEN_VERSION, flags];
+        put_str(&mut b, mid.unwrap_or("0"))?;
+        put_str(&mut b, ufrag.ok_or_else(|| missing("ice-ufrag"))?)?;
+        put_str(&mut b, pwd.ok_or_else(|| missing("ice-pwd"))?)?;
+        b.extend_from_slice(&fingerprint.ok_or_else(|| missing("fingerprint"))?);
+
+        if candidates.len() > u8::MAX as usize {
+            return Err(encode_error("too many candidates"));
+        }
+        b.push(candidates.len() as u8);
+        for c in &candidates {
+            let mut header = if c.srflx { CANDIDATE_SRFLX } else { 0 };
+            if let Ok(ip) = c.address.parse::<Ipv4Addr>() {
+                b.push(header | ADDRESS_IPV4);
+                b.extend_from_slice(&c.priority.to_be_bytes());
+                b.extend_from_slice(&ip.octets());
+            } else if let Ok(ip) = c.address.parse::<Ipv6Addr>() {
+                b.push(header | ADDRESS_IPV6);
+                b.extend_from_slice(&c.priority.to_be_bytes());
+                b.extend_from_slice(&ip.octets());
+            } else {
+                header |= ADDRESS_HOSTNAME;
+                b.push(header);
+                b.extend_from_slice(&c.priority.to_be_bytes());
+                put_str(&mut b, &c.address)?;
+            }
+            b.extend_from_slice(&c.port.to_be_bytes());
+        }
+
+        let checksum = crc32fast::hash(&b);
+        b.extend_from_slice(&checksum.to_be_bytes());
+        Ok(URL_SAFE_NO_PAD.encode(b))
+    }
+
+    fn decode(&self, encoded: &str) -> Result<String> {
+        let b = URL_SAFE_NO_PAD.decode(encoded.trim()).map_err(|e| match e {
+            // base64 cut off in the middle of a byte
+            DecodeError::InvalidLength | DecodeError::InvalidLastSymbol(..) => {
+                decode_error("it was probably not pasted completely")
+            }
+            _ => decode_error("not a connection token, it contains invalid characters"),
+        })?;
+        if b.len() < 6 {
+            return Err(decode_error("too short, it was probably not pasted completely"));
+        }
+        let (b, checksum) = b.split_at(b.len() - 4);
+        if crc32fast::hash(b).to_be_bytes() != checksum {
+            return Err(decode_error(
+                "checksum mismatch, it was probably not pasted completely",
+            ));
+        }
+        if b[0] != TOKEN_VERSION {
+            return Err(decode_error(&format!("unsupported version {}", b[0])));
+        }
+
+        let mut r = Reader { b, pos: 1 };
+        let flags = r.u8()?;
+        let mid = r.str()?;
+        let ufrag = r.str()?;
+        let pwd = r.str()?;
+        let fingerprint = r.bytes(FINGERPRINT_LEN)?;
+        let setup = match (flags >> SETUP_SHIFT) & 0b11 {
+            SETUP_ACTIVE => "active",
+            SETUP_PASSIVE => "passive",
+            _ => "actpass",
+        };
+
+        let mut sdp = String::new();
+        let _ = write!(
+            sdp,
+            "v=0\r\no=- {} 2 IN IP4 0.0.0.0\r\ns=-\r\nt=0 0\r\n",
+            rand::random::<u32>()
+        );
+        let fingerprint: Vec<String> = fingerprint.iter().map(|v| format!("{:02X}", v)).collect();
+        let _ = write!(sdp, "a=fingerprint:sha-256 {}\r\n", fingerprint.join(":"));
+        let _ = write!(sdp, "a=group:BUNDLE {}\r\n", mid);
+        sdp.push_str("m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\nc=IN IP4 0.0.0.0\r\n");
+        let _ = write!(sdp, "a=setup:{}\r\na=mid:{}\r\na=sendrecv\r\na=sctp-port:5000\r\n", setup, mid);
+        let _ = write!(sdp, "a=ice-ufrag:{}\r\na=ice-pwd:{}\r\n", ufrag, pwd);
+
+        let count = r.u8()?;
+        for foundation in 1..=count {
+            let header = r.u8()?;
+            let priority = u32::from_be_bytes(r.bytes(4)?.try_into().unwrap());
+            let address = match header & ADDRESS_MASK {
+                ADDRESS_IPV4 => Ipv4Addr::from(<[u8; 4]>::try_from(r.bytes(4)?).unwrap()).to_string(),
+                ADDRESS_IPV6 => Ipv6Addr::from(<[u8; 16]>::try_from(r.bytes(16)?).unwrap()).to_string(),
+                ADDRESS_HOSTNAME => r.str()?,
+                _ => return Err(decode_error("unknown candidate address kind")),
+            };
+            let port = u16::from_be_bytes(r.bytes(2)?.try_into().unwrap());
+            let _ = write!(
+                sdp,
+                "a=candidate:{} 1 udp {} {} {} typ ",
+                foundation, priority, address, port
+            );
+            if header & CANDIDATE_SRFLX != 0 {
+                sdp.push_str("srflx raddr 0.0.0.0 rport 0\r\n");
+            } else {
+                sdp.push_str("host\r\n");
+            }
+        }
+        sdp.push_str("a=end-of-candidates\r\n");
+        if r.pos != b.len() {
+            return Err(decode_error("unexpected trailing data"));
+        }
+
+        let desc = if flags & FLAG_ANSWER != 0 {
+            RTCSessionDescription::answer(sdp)
+        } else {
+            RTCSessionDescription::offer(sdp)
+        }
+        .map_err(|e| decode_error(&e.to_string()))?;
+        serde_json::to_string(&desc).map_err(|e| decode_error(&e.to_string()))
+    }
+}
+
+struct Reader<'a> {
+    b: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.pos + len > self.b.len() {
+            return Err(decode_error("truncated"));
+        }
+        let v = &self.b[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(v)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn str(&mut self) -> Result<String> {
+        let len = self.u8()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec())
+            .map_err(|_| decode_error("contains invalid text"))
+    }
+}
+
+fn put_str(b: &mut Vec<u8>, s: &str) -> Result<()> {
+    if s.len() > u8::MAX as usize {
+        return Err(encode_error(&format!("'{}' is too long", s)));
+    }
+    b.push(s.len() as u8);
+    b.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn parse_fingerprint(v: &str) -> Result<[u8; FINGERPRINT_LEN]> {
+    let (algorithm, value) = v
+        .split_once(' ')
+        .ok_or_else(|| encode_error("malformed fingerprint"))?;
+    if !algorithm.eq_ignore_ascii_case("sha-256") {
+        return Err(encode_error(&format!("unsupported fingerprint algorithm {}", algorithm)));
+    }
+    let bytes = value
+        .split(':')
+        .map(|v| u8::from_str_radix(v, 16))
+        .collect::<std::result::Result<Vec<u8>, _>>()
+        .map_err(|_| encode_error("malformed fingerprint"))?;
+    bytes
+        .try_into()
+        .map_err(|_| encode_error("malformed fingerprint"))
+}
+
+/// Keep component 1 UDP host and srflx candidates, everything else is left out of the token
+fn parse_candidate(v: &str) -> Option<Candidate> {
+    let fields: Vec<&str> = v.split_whitespace().collect();
+    if fields.len() < 8 || fields[1] != "1" || !fields[2].eq_ignore_ascii_case("udp") || fields[6] != "typ" {
+        return None;
+    }
+    let srflx = match fields[7] {
+        "host" => false,
+        "srflx" => true,
+        _ => return None,
+    };
+    Some(Candidate {
+        srflx,
+        priority: fields[3].parse().ok()?,
+        address: fields[4].to_owned(),
+        port: fields[5].parse().ok()?,
+    })
+}
+
+fn encode_error(msg: &str) -> Error {
+    Error::SignalingEncode(format!("could not create connection token: {}", msg))
+}
+
+fn decode_error(msg: &str) -> Error {
+    Error::SignalingDecode(format!("invalid connection token: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FINGERPRINT: &str = "sha-256 0A:1B:2C:3D:4E:5F:60:71:82:93:A4:B5:C6:D7:E8:F9:0A:1B:2C:3D:4E:5F:60:71:82:93:A4:B5:C6:D7:E8:F9";
+
+    fn description(sdp_type: &str, setup: &str) -> String {
+        let sdp = format!(
+            "v=0\r\no=- 123 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\na=fingerprint:{}\r\n\
+             a=group:BUNDLE 0\r\nm=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\n\
+             c=IN IP4 0.0.0.0\r\na=setup:{}\r\na=mid:0\r\na=sendrecv\r\na=sctp-port:5000\r\n\
+             a=ice-ufrag:AbCdEfGh\r\na=ice-pwd:0123456789abcdefghijklmnopqrstuv\r\n\
+             a=candidate:1 1 udp 2130706431 192.168.1.20 50000 typ host\r\n\
+             a=candidate:2 1 udp 2130706431 fe80::1 50001 typ host\r\n\
+             a=candidate:3 1 udp 2130706431 4f1e0c1a-5b2d.local 50002 typ host\r\n\
+             a=candidate:4 1 udp 1694498815 203.0.113.7 61000 typ srflx raddr 0.0.0.0 rport 50000\r\n\
+             a=candidate:5 1 udp 16777215 198.51.100.1 3478 typ relay raddr 0.0.0.0 rport 0\r\n\
+             a=candidate:6 1 tcp 1518280447 192.168.1.20 9 typ host tcptype active\r\n\
+             a=end-of-candidates\r\n",
+            FINGERPRINT, setup
+        );
+        format!(
+            r#"{{"type":"{}","sdp":{}}}"#,
+            sdp_type,
+            serde_json::to_string(&sdp).unwrap()
+        )
+    }
+
+    fn decoded_sdp(token: &str) -> (RTCSdpType, String) {
+        let json = TokenCodec.decode(token).unwrap();
+        let desc = serde_json::from_str::<RTCSessionDescription>(&json).unwrap();
+        (desc.sdp_type, desc.sdp)
+    }
+
+    fn decode_error_message(token: &str) -> String {
+        match TokenCodec.decode(token) {
+            Err(Error::SignalingDecode(message)) => message,
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("{} decoded", token),
+        }
+    }
+
+    /// A token with a valid checksum around `body`
+    fn token_of(body: &[u8]) -> String {
+        let mut b = body.to_vec();
+        b.extend_from_slice(&crc32fast::hash(body).to_be_bytes());
+        URL_SAFE_NO_PAD.encode(b)
+    }
+
+    #[test]
+    fn offer_roundtrip_keeps_what_a_data_channel_needs() {
+        let token = TokenCodec.encode(&description("offer", "actpass")).unwrap();
+        let (sdp_type, sdp) = decoded_sdp(&token);
+        assert_eq!(sdp_type, RTCSdpType::Offer);
+        assert!(sdp.contains("a=ice-ufrag:AbCdEfGh\r\n"));
+        assert!(sdp.contains("a=ice-pwd:0123456789abcdefghijklmnopqrstuv\r\n"));
+        assert!(sdp.contains(&format!("a=fingerprint:{}\r\n", FINGERPRINT)));
+        assert!(sdp.contains("a=setup:actpass\r\n"));
+        assert!(sdp.contains("a=mid:0\r\n"));
+        assert!(sdp.contains(" 2130706431 192.168.1.20 50000 typ host\r\n"));
+        assert!(sdp.contains(" 2130706431 fe80::1 50001 typ host\r\n"));
+        assert!(sdp.contains(" 2130706431 4f1e0c1a-5b2d.local 50002 typ host\r\n"));
+        assert!(sdp.contains(" 1694498815 203.0.113.7 61000 typ srflx"));
+        // relay and TCP candidates are left out
+        assert_eq!(sdp.matches("a=candidate:").count(), 4);
+    }
+
+    #[test]
+    fn answer_roundtrip_keeps_type_and_setup() {
+        for setup in ["active", "passive"] {
+            let token = TokenCodec.encode(&description("answer", setup)).unwrap();
+            let (sdp_type, sdp) = decoded_sdp(&token);
+            assert_eq!(sdp_type, RTCSdpType::Answer);
+            assert!(sdp.contains(&format!("a=setup:{}\r\n", setup)));
+        }
+    }
+
+    #[test]
+    fn token_is_shorter_than_base64() {
+        let json = description("offer", "actpass");
+        let token = TokenCodec.encode(&json).unwrap();
+        assert!(token.len() * 2 < crate::Base64Codec.encode(&json).unwrap().len());
+    }
+
+    #[test]
+    fn truncated_token_is_reported() {
+        let token = TokenCodec.encode(&description("offer", "actpass")).unwrap();
+        for len in [token.len() - 1, token.len() / 2, 10] {
+            assert!(decode_error_message(&token[..len]).contains("not pasted completely"));
+        }
+        assert!(decode_error_message("AAAA").contains("too short"));
+    }
+
+    #[test]
+    fn corrupt_token_is_reported() {
+        let token = TokenCodec.encode(&description("offer", "actpass")).unwrap();
+        let mut corrupt = token.into_bytes();
+        let middle = corrupt.len() / 2;
+        corrupt[middle] = if corrupt[middle] == b'A' { b'B' } else { b'A' };
+        let corrupt = String::from_utf8(corrupt).unwrap();
+        assert!(decode_error_message(&corrupt).contains("checksum mismatch"));
+        assert!(decode_error_message("not a token!").contains("invalid characters"));
+    }
+
+    #[test]
+    fn other_version_is_reported() {
+        let token = TokenCodec.encode(&description("offer", "actpass")).unwrap();
+        let mut body = URL_SAFE_NO_PAD.decode(token).unwrap();
+        body.truncate(body.len() - 4);
+        body[0] = TOKEN_VERSION + 1;
+        assert!(decode_error_message(&token_of(&body)).contains("unsupported version 2"));
+    }
+
+    #[test]
+    fn inconsistent_token_is_reported() {
+        // a valid checksum around fields that end too early
+        assert!(decode_error_message(&token_of(&[TOKEN_VERSION, 0, 200, b'x'])).contains("truncated"));
+        let token = TokenCodec.encode(&description("offer", "actpass")).unwrap();
+        let mut body = URL_SAFE_NO_PAD.decode(token).unwrap();
+        body.truncate(body.len() - 4);
+        body.push(0);
+        assert!(decode_error_message(&token_of(&body)).contains("trailing data"));
+    }
+
+    #[test]
+    fn description_without_credentials_is_not_encoded() {
+        let json = description("offer", "actpass").replace("a=ice-pwd:", "a=x-pwd:");
+        match TokenCodec.encode(&json) {
+            Err(Error::SignalingEncode(message)) => assert!(message.contains("ice-pwd")),
+            other => panic!("unexpected {:?}", other.map_err(|e| e.to_string())),
+        }
+    }
+}
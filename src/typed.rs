@@ -0,0 +1,301 @@
+//! Data channels carrying serde types instead of raw bytes, see `TypedChannel`.
+//!
+//! Codecs are behind cargo features: `Json` (`json`, on by default), `MessagePack` (`msgpack`),
+//! `Bincode` (`bincode`) and `Cbor` (`cbor`). Both sides have to use the same one.
+
+use crate::queue::{EventQueue, EventSender};
+use crate::{DataChannel, Error, Peer, Result};
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio_stream::Stream;
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+
+/// Turns messages into data channel payloads and back
+pub trait MessageCodec: Send + Sync + 'static {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T>;
+
+    /// Send as text messages instead of binary ones, for codecs producing UTF-8
+    fn is_text(&self) -> bool {
+        false
+    }
+}
+
+/// Text messages of JSON, readable by any browser
+#[cfg(feature = "json")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl MessageCodec for Json {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(|e| Error::MessageEncode(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        serde_json::from_slice(data).map_err(|e| Error::MessageDecode(e.to_string()))
+    }
+
+    fn is_text(&self) -> bool {
+        true
+    }
+}
+
+/// MessagePack with field names, so both sides may add fields independently
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl MessageCodec for MessagePack {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>> {
+        rmp_serde::to_vec_named(value).map_err(|e| Error::MessageEncode(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        rmp_serde::from_slice(data).map_err(|e| Error::MessageDecode(e.to_string()))
+    }
+}
+
+/// The most compact codec, but both sides need the exact same types
+#[cfg(feature = "bincode")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl MessageCodec for Bincode {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>> {
+        bincode::serialize(value).map_err(|e| Error::MessageEncode(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        bincode::deserialize(data).map_err(|e| Error::MessageDecode(e.to_string()))
+    }
+}
+
+/// Self-describing like JSON but binary, for talking to other CBOR implementations
+#[cfg(feature = "cbor")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl MessageCodec for Cbor {
+    fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        ciborium::ser::into_writer(value, &mut data)
+            .map_err(|e| Error::MessageEncode(e.to_string()))?;
+        Ok(data)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, data: &[u8]) -> Result<T> {
+        ciborium::de::from_reader(data).map_err(|e| Error::MessageDecode(e.to_string()))
+    }
+}
+
+/// Something that arrived on a `TypedChannel`
+#[derive(Debug)]
+pub enum TypedEvent<In> {
+    Message(In),
+    /// A message that did not decode as `In`, the channel stays usable
+    DecodeError {
+        error: Error,
+        data: Bytes,
+    },
+}
+
+type EncodeFn<Out> = Arc<dyn Fn(&Out) -> Result<Vec<u8>> + Send + Sync>;
+
+/// Sends `Out` and receives `In` over a data channel of a `Peer`, received messages are a
+/// stream of `TypedEvent`s. One of the [channel wrappers](crate#channel-wrappers).
+/// Received messages wait in a queue with the peer's `ConfigurationBuilder::event_queue`
+/// capacity and policy.
+pub struct TypedChannel<In, Out> {
+    sender: TypedSender<Out>,
+    events: Arc<EventQueue<TypedEvent<In>>>,
+}
+
+impl<In, Out> TypedChannel<In, Out>
+where
+    In: DeserializeOwned + Send + 'static,
+    Out: Serialize,
+{
+    /// Wrap `channel` of `peer`, both sides have to use the same `codec`
+    pub fn new<C: MessageCodec>(
+        peer: &Peer,
+        channel: DataChannel,
+        codec: C,
+    ) -> TypedChannel<In, Out> {
+        let codec = Arc::new(codec);
+        let events = FinishOnDrop(peer.wrapper_events());
+        let events_rx = events.0.queue().clone();
+        let decoder = codec.clone();
+        channel.on_message(Box::new(move |msg: DataChannelMessage| {
+            let event = match decoder.decode(&msg.data) {
+                Ok(message) => TypedEvent::Message(message),
+                Err(error) => TypedEvent::DecodeError {
+                    error,
+                    data: msg.data,
+                },
+            };
+            // waits for room under `BackpressurePolicy::Block`, holding up the channel
+            let events = events.0.clone();
+            Box::pin(async move { events.send(event).await })
+        }));
+
+        let is_text = codec.is_text();
+        TypedChannel {
+            sender: TypedSender {
+                channel,
+                encode: Arc::new(move |message| codec.encode(message)),
+                is_text,
+            },
+            events: events_rx,
+        }
+    }
+
+    pub async fn send(&self, message: &Out) -> Result<usize> {
+        self.sender.send(message).await
+    }
+
+    /// For sending from other tasks while this one reads
+    pub fn sender(&self) -> TypedSender<Out> {
+        self.sender.clone()
+    }
+
+    pub fn channel(&self) -> &DataChannel {
+        &self.sender.channel
+    }
+
+    /// Number of messages discarded because the queue was full
+    pub fn dropped_messages(&self) -> u64 {
+        self.events.dropped()
+    }
+}
+
+impl<In, Out> Stream for TypedChannel<In, Out> {
+    type Item = TypedEvent<In>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_pop(cx)
+    }
+}
+
+impl<In, Out> Drop for TypedChannel<In, Out> {
+    fn drop(&mut self) {
+        // nobody is reading anymore, release a message handler blocked on a full queue
+        self.events.close();
+    }
+}
+
+/// Ends the stream once the channel lets go of its message handler
+struct FinishOnDrop<T>(EventSender<T>);
+
+impl<T> Drop for FinishOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.finish();
+    }
+}
+
+/// Sending half of a `TypedChannel`
+pub struct TypedSender<Out> {
+    channel: DataChannel,
+    encode: EncodeFn<Out>,
+    is_text: bool,
+}
+
+impl<Out> Clone for TypedSender<Out> {
+    fn clone(&self) -> Self {
+        TypedSender {
+            channel: self.channel.clone(),
+            encode: self.encode.clone(),
+            is_text: self.is_text,
+        }
+    }
+}
+
+impl<Out> TypedSender<Out> {
+    pub async fn send(&self, message: &Out) -> Result<usize> {
+        let data = (self.encode)(message)?;
+        let sent = if self.is_text {
+            let text = String::from_utf8(data).map_err(|e| Error::MessageEncode(e.to_string()))?;
+            self.channel.send_text(text).await
+        } else {
+            self.channel.send(&Bytes::from(data)).await
+        };
+        sent.map_err(Error::Channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Command {
+        Join { room: String },
+        Say(String),
+        Move(i32, i32),
+        Leave,
+    }
+
+    fn commands() -> Vec<Command> {
+        vec![
+            Command::Join {
+                room: "lobby".to_owned(),
+            },
+            Command::Say("hi ünïcode".to_owned()),
+            Command::Move(-3, 7),
+            Command::Leave,
+        ]
+    }
+
+    fn round_trip(codec: impl MessageCodec) {
+        for command in commands() {
+            let data = codec.encode(&command).unwrap();
+            if codec.is_text() {
+                assert!(std::str::from_utf8(&data).is_ok());
+            }
+            assert_eq!(codec.decode::<Command>(&data).unwrap(), command);
+        }
+    }
+
+    fn rejects_malformed(codec: impl MessageCodec) {
+        match codec.decode::<Command>(&[0xff, 0x00, 0x13]) {
+            Err(Error::MessageDecode(_)) => {}
+            other => panic!("expected a decode error, got {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_round_trip() {
+        round_trip(Json);
+        rejects_malformed(Json);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn msgpack_round_trip() {
+        round_trip(MessagePack);
+        rejects_malformed(MessagePack);
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode_round_trip() {
+        round_trip(Bincode);
+        rejects_malformed(Bincode);
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn cbor_round_trip() {
+        round_trip(Cbor);
+        rejects_malformed(Cbor);
+    }
+}
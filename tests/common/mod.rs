@@ -0,0 +1,49 @@
+//! Helpers shared by the integration tests
+// each test crate uses only some of them
+#![allow(dead_code)]
+
+use cyberdeck::*;
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Wait until channels with all of `labels` are open, fails on errors
+pub async fn wait_for_channels(events: &mut PeerEventStream, labels: &[&str]) {
+    let mut open = HashSet::new();
+    let waiting = async {
+        while let Some(e) = events.next().await {
+            match e {
+                PeerEvent::DataChannelStateChange(c)
+                    if c.ready_state() == RTCDataChannelState::Open =>
+                {
+                    open.insert(c.label().to_owned());
+                    if labels.iter().all(|label| open.contains(*label)) {
+                        return;
+                    }
+                }
+                PeerEvent::Error(e) => panic!("connecting failed: {}", e),
+                _ => {}
+            }
+        }
+        panic!("events ended");
+    };
+    tokio::time::timeout(Duration::from_secs(20), waiting)
+        .await
+        .unwrap_or_else(|_| panic!("channels {:?} did not open, only {:?}", labels, open));
+}
+
+/// Wait until the channel `label` is open and return it
+pub async fn open_channel(events: &mut PeerEventStream, label: &str) -> DataChannel {
+    let waiting = async {
+        while let Some(e) = events.next().await {
+            if let PeerEvent::DataChannelStateChange(c) = e {
+                if c.label() == label && c.ready_state() == RTCDataChannelState::Open {
+                    return c;
+                }
+            }
+        }
+        panic!("events ended");
+    };
+    tokio::time::timeout(Duration::from_secs(20), waiting)
+        .await
+        .unwrap_or_else(|_| panic!("channel {} did not open", label))
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use common::open_channel;
+use cyberdeck::signaling::InMemorySignaler;
+use cyberdeck::*;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Both ends of a `FileTransfer` on a fresh connection
+async fn transfer_pair() -> (FileTransfer, FileTransfer, (Peer, Peer)) {
+    let (mut a, mut a_events) = Peer::new_with_stream(Configuration::default())
+        .await
+        .unwrap();
+    let (mut b, mut b_events) = Peer::new_with_stream(Configuration::default())
+        .await
+        .unwrap();
+    a.create_channel("files").await.unwrap();
+    let (a_signaler, b_signaler) = InMemorySignaler::pair();
+    a.connect_with(a_signaler, NegotiationRole::Impolite)
+        .await
+        .unwrap();
+    b.connect_with(b_signaler, NegotiationRole::Polite)
+        .await
+        .unwrap();
+    let (sender, receiver) = tokio::join!(
+        open_channel(&mut a_events, "files"),
+        open_channel(&mut b_events, "files")
+    );
+    (
+        FileTransfer::new(sender),
+        FileTransfer::new(receiver),
+        (a, b),
+    )
+}
+
+fn download_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("cyberdeck-{}-{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn contents() -> Vec<u8> {
+    (0..200_000u32).map(|n| (n % 251) as u8).collect()
+}
+
+fn part_file(path: &Path, data: &[u8]) -> PathBuf {
+    let sha256: String = Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let mut part = path.as_os_str().to_owned();
+    part.push(format!(".{}.part", &sha256[..16]));
+    PathBuf::from(part)
+}
+
+#[tokio::test]
+async fn existing_file_is_replaced_not_resumed() {
+    let (mut sender, mut receiver, _peers) = transfer_pair().await;
+    let dir = download_dir("replace");
+    let path = dir.join("map.png");
+    std::fs::write(&path, b"an older file of the same name").unwrap();
+    let data = contents();
+
+    let (sent, saved) = tokio::join!(sender.send_bytes("map.png", &data), async {
+        let incoming = receiver.receive().await?;
+        // the older file is left alone until the new one is verified
+        assert_eq!(
+            std::fs::read(&path).unwrap(),
+            b"an older file of the same name"
+        );
+        incoming.save(&path).await
+    });
+    sent.unwrap();
+    saved.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    assert!(!part_file(&path, &data).exists());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn save_resumes_from_its_part_file() {
+    let (mut sender, receiver, _peers) = transfer_pair().await;
+    let progress = Arc::new(Mutex::new(vec![]));
+    let reported = progress.clone();
+    let mut receiver = receiver.on_progress(move |p| reported.lock().unwrap().push(p.transferred));
+    let dir = download_dir("resume");
+    let path = dir.join("map.png");
+    let data = contents();
+    std::fs::write(part_file(&path, &data), &data[..120_000]).unwrap();
+
+    let (sent, saved) = tokio::join!(sender.send_bytes("map.png", &data), async {
+        receiver.receive().await?.save(&path).await
+    });
+    sent.unwrap();
+    saved.unwrap();
+    assert_eq!(progress.lock().unwrap().first(), Some(&120_000));
+    assert_eq!(std::fs::read(&path).unwrap(), data);
+    assert!(!part_file(&path, &data).exists());
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,139 @@
+mod common;
+
+use common::wait_for_channels;
+use cyberdeck::*;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+
+type SharedPeer = Arc<Mutex<Peer>>;
+type Sent = Arc<std::sync::Mutex<Vec<String>>>;
+
+/// Two peers whose negotiation callbacks deliver to each other, candidates are part of the
+/// descriptions. Also returns what the polite side sent.
+async fn negotiating_pair() -> (
+    (SharedPeer, PeerEventStream),
+    (SharedPeer, PeerEventStream),
+    Sent,
+) {
+    let (to_polite, to_polite_rx) = mpsc::unbounded_channel::<String>();
+    let (to_impolite, to_impolite_rx) = mpsc::unbounded_channel::<String>();
+    let sent_by_polite = Sent::default();
+    let config = |role, to_other: mpsc::UnboundedSender<String>| {
+        let sent = sent_by_polite.clone();
+        ConfigurationBuilder::new()
+            .negotiation(role, move |description| {
+                if role == NegotiationRole::Polite {
+                    sent.lock().unwrap().push(description.clone());
+                }
+                let sent = to_other
+                    .send(description)
+                    .map_err(|_| Error::Signaling("gone".to_owned()));
+                async move { sent }
+            })
+            .build()
+    };
+    let (impolite, impolite_events) =
+        Peer::new_with_stream(config(NegotiationRole::Impolite, to_polite))
+            .await
+            .unwrap();
+    let (polite, polite_events) =
+        Peer::new_with_stream(config(NegotiationRole::Polite, to_impolite))
+            .await
+            .unwrap();
+    let impolite = Arc::new(Mutex::new(impolite));
+    let polite = Arc::new(Mutex::new(polite));
+    tokio::spawn(deliver(to_polite_rx, polite.clone()));
+    tokio::spawn(deliver(to_impolite_rx, impolite.clone()));
+    (
+        (impolite, impolite_events),
+        (polite, polite_events),
+        sent_by_polite,
+    )
+}
+
+async fn deliver(mut descriptions: mpsc::UnboundedReceiver<String>, peer: SharedPeer) {
+    while let Some(description) = descriptions.recv().await {
+        peer.lock()
+            .await
+            .receive_description(&description)
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn glare_between_native_peers_is_resolved() {
+    let ((impolite, mut impolite_events), (polite, mut polite_events), _) =
+        negotiating_pair().await;
+    // both sides offer at the same time
+    let (a, b) = tokio::join!(
+        async { impolite.lock().await.create_channel("from-impolite").await },
+        async { polite.lock().await.create_channel("from-polite").await },
+    );
+    a.unwrap();
+    b.unwrap();
+    let labels = ["from-impolite", "from-polite"];
+    tokio::join!(
+        wait_for_channels(&mut impolite_events, &labels),
+        wait_for_channels(&mut polite_events, &labels),
+    );
+}
+
+#[tokio::test]
+async fn polite_side_can_offer_first() {
+    let ((_impolite, mut impolite_events), (polite, mut polite_events), _) =
+        negotiating_pair().await;
+    polite.lock().await.create_channel("first").await.unwrap();
+    tokio::join!(
+        wait_for_channels(&mut impolite_events, &["first"]),
+        wait_for_channels(&mut polite_events, &["first"]),
+    );
+}
+
+#[tokio::test]
+async fn either_side_renegotiates_a_live_connection() {
+    let ((impolite, mut impolite_events), (polite, mut polite_events), _) =
+        negotiating_pair().await;
+    impolite.lock().await.create_channel("first").await.unwrap();
+    tokio::join!(
+        wait_for_channels(&mut impolite_events, &["first"]),
+        wait_for_channels(&mut polite_events, &["first"]),
+    );
+
+    polite.lock().await.create_channel("later").await.unwrap();
+    tokio::join!(
+        wait_for_channels(&mut impolite_events, &["later"]),
+        wait_for_channels(&mut polite_events, &["later"]),
+    );
+}
+
+#[tokio::test]
+async fn polite_side_without_trickle_sends_its_candidates() {
+    let ((_impolite, mut impolite_events), (polite, mut polite_events), sent_by_polite) =
+        negotiating_pair().await;
+    polite.lock().await.create_channel("first").await.unwrap();
+    tokio::join!(
+        wait_for_channels(&mut impolite_events, &["first"]),
+        wait_for_channels(&mut polite_events, &["first"]),
+    );
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(20);
+    loop {
+        let offers_with_candidates = sent_by_polite
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|description| Base64Codec.decode(description).unwrap())
+            .filter(|description| description.contains("a=candidate"))
+            .count();
+        if offers_with_candidates > 0 {
+            break;
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "no candidates were sent"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
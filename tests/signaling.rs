@@ -0,0 +1,103 @@
+mod common;
+
+use async_trait::async_trait;
+use common::wait_for_channels;
+use cyberdeck::signaling::{Description, InMemorySignaler, Signal, Signaler};
+use cyberdeck::*;
+use std::time::Duration;
+
+/// Two peers connected over an `InMemorySignaler` pair, each with a channel created up front
+async fn connected_pair(trickle_ice: bool) -> ((Peer, PeerEventStream), (Peer, PeerEventStream)) {
+    let config = || ConfigurationBuilder::new().trickle_ice(trickle_ice).build();
+    let (mut a, a_events) = Peer::new_with_stream(config()).await.unwrap();
+    let (mut b, b_events) = Peer::new_with_stream(config()).await.unwrap();
+    a.create_channel("from-a").await.unwrap();
+    b.create_channel("from-b").await.unwrap();
+    let (a_signaler, b_signaler) = InMemorySignaler::pair();
+    a.connect_with(a_signaler, NegotiationRole::Impolite)
+        .await
+        .unwrap();
+    b.connect_with(b_signaler, NegotiationRole::Polite)
+        .await
+        .unwrap();
+    ((a, a_events), (b, b_events))
+}
+
+#[tokio::test]
+async fn peers_connect_over_in_memory_signaler() {
+    let ((_a, mut a_events), (_b, mut b_events)) = connected_pair(false).await;
+    let labels = ["from-a", "from-b"];
+    tokio::join!(
+        wait_for_channels(&mut a_events, &labels),
+        wait_for_channels(&mut b_events, &labels),
+    );
+}
+
+#[tokio::test]
+async fn peers_trickle_candidates_over_in_memory_signaler() {
+    let ((_a, mut a_events), (_b, mut b_events)) = connected_pair(true).await;
+    let labels = ["from-a", "from-b"];
+    tokio::join!(
+        wait_for_channels(&mut a_events, &labels),
+        wait_for_channels(&mut b_events, &labels),
+    );
+}
+
+#[tokio::test]
+async fn channels_added_later_are_negotiated_over_the_signaler() {
+    let ((mut a, mut a_events), (mut b, mut b_events)) = connected_pair(true).await;
+    let labels = ["from-a", "from-b"];
+    tokio::join!(
+        wait_for_channels(&mut a_events, &labels),
+        wait_for_channels(&mut b_events, &labels),
+    );
+
+    a.create_channel("later-a").await.unwrap();
+    tokio::join!(
+        wait_for_channels(&mut a_events, &["later-a"]),
+        wait_for_channels(&mut b_events, &["later-a"]),
+    );
+    b.create_channel("later-b").await.unwrap();
+    tokio::join!(
+        wait_for_channels(&mut a_events, &["later-b"]),
+        wait_for_channels(&mut b_events, &["later-b"]),
+    );
+}
+
+struct BrokenSignaler;
+
+#[async_trait]
+impl Signaler for BrokenSignaler {
+    async fn send_description(&mut self, _description: Description) -> Result<()> {
+        Ok(())
+    }
+
+    async fn send_candidate(&mut self, _candidate: String) -> Result<()> {
+        Ok(())
+    }
+
+    async fn receive(&mut self) -> Result<Option<Signal>> {
+        Err(Error::Signaling("connection lost".to_owned()))
+    }
+}
+
+#[tokio::test]
+async fn receive_error_ends_signaling() {
+    let (mut peer, mut events) = Peer::new_with_stream(Configuration::default())
+        .await
+        .unwrap();
+    peer.connect_with(BrokenSignaler, NegotiationRole::Polite)
+        .await
+        .unwrap();
+
+    let mut errors = 0;
+    let _ = tokio::time::timeout(Duration::from_millis(500), async {
+        while let Some(e) = events.next().await {
+            if let PeerEvent::Error(_) = e {
+                errors += 1;
+            }
+        }
+    })
+    .await;
+    assert_eq!(errors, 1);
+}
@@ -0,0 +1,96 @@
+#![cfg(feature = "json")]
+
+mod common;
+
+use common::open_channel;
+use cyberdeck::signaling::InMemorySignaler;
+use cyberdeck::typed::Json;
+use cyberdeck::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Command {
+    Say(String),
+    Move(i32, i32),
+}
+
+type Commands = TypedChannel<Command, Command>;
+
+/// Both ends of a `TypedChannel` on a fresh connection, the receiving side uses `config`
+async fn typed_pair(config: Configuration) -> (Commands, Commands, (Peer, Peer)) {
+    let (mut a, mut a_events) = Peer::new_with_stream(Configuration::default())
+        .await
+        .unwrap();
+    let (mut b, mut b_events) = Peer::new_with_stream(config).await.unwrap();
+    a.create_channel("commands").await.unwrap();
+    let (a_signaler, b_signaler) = InMemorySignaler::pair();
+    a.connect_with(a_signaler, NegotiationRole::Impolite)
+        .await
+        .unwrap();
+    b.connect_with(b_signaler, NegotiationRole::Polite)
+        .await
+        .unwrap();
+    let (sender, receiver) = tokio::join!(
+        open_channel(&mut a_events, "commands"),
+        open_channel(&mut b_events, "commands")
+    );
+    (
+        TypedChannel::new(&a, sender, Json),
+        TypedChannel::new(&b, receiver, Json),
+        (a, b),
+    )
+}
+
+async fn next(commands: &mut Commands) -> Option<TypedEvent<Command>> {
+    tokio::time::timeout(Duration::from_secs(5), commands.next())
+        .await
+        .expect("no message arrived")
+}
+
+#[tokio::test]
+async fn malformed_messages_arrive_as_decode_errors() {
+    let (sender, mut receiver, _peers) = typed_pair(Configuration::default()).await;
+    sender
+        .channel()
+        .send_text("{\"Jump\": 3}".to_owned())
+        .await
+        .unwrap();
+    sender.send(&Command::Move(1, -1)).await.unwrap();
+
+    match next(&mut receiver).await {
+        Some(TypedEvent::DecodeError { error, data }) => {
+            assert!(matches!(error, Error::MessageDecode(_)));
+            assert_eq!(&data[..], b"{\"Jump\": 3}");
+        }
+        other => panic!("expected a decode error, got {:?}", other),
+    }
+    // the channel is still usable
+    match next(&mut receiver).await {
+        Some(TypedEvent::Message(command)) => assert_eq!(command, Command::Move(1, -1)),
+        other => panic!("expected a message, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn received_messages_follow_the_event_queue_bound() {
+    let config = ConfigurationBuilder::new()
+        .event_queue(4, BackpressurePolicy::DropNewest)
+        .build();
+    let (sender, mut receiver, _peers) = typed_pair(config).await;
+    for n in 0..10 {
+        sender.send(&Command::Say(n.to_string())).await.unwrap();
+    }
+    // nobody reads until everything arrived
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let mut received = vec![];
+    while let Ok(Some(TypedEvent::Message(command))) =
+        tokio::time::timeout(Duration::from_millis(200), receiver.next()).await
+    {
+        received.push(command);
+    }
+    let expected: Vec<Command> = (0..4).map(|n| Command::Say(n.to_string())).collect();
+    assert_eq!(received, expected);
+    assert_eq!(receiver.dropped_messages(), 6);
+}
@@ -0,0 +1,107 @@
+#![cfg(all(feature = "axum", feature = "websocket"))]
+
+mod common;
+
+use common::wait_for_channels;
+use cyberdeck::signaling::websocket::WebSocketSignaling;
+use cyberdeck::signaling::{server, SignalMessage};
+use cyberdeck::*;
+use std::time::Duration;
+
+/// Serve the signaling server on a free local port, returns the url of its WebSocket endpoint
+fn start_server() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}/ws", listener.local_addr().unwrap());
+    let app = server::router(|_, _| async {});
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service()),
+    );
+    url
+}
+
+/// Wait until the channel `label` is open, returns how many errors were reported before
+async fn count_errors_until_open(events: &mut PeerEventStream, label: &str) -> usize {
+    let mut errors = 0;
+    let waiting = async {
+        while let Some(e) = events.next().await {
+            match e {
+                PeerEvent::DataChannelStateChange(c)
+                    if c.label() == label && c.ready_state() == RTCDataChannelState::Open =>
+                {
+                    return;
+                }
+                PeerEvent::Error(_) => errors += 1,
+                _ => {}
+            }
+        }
+        panic!("events ended");
+    };
+    tokio::time::timeout(Duration::from_secs(20), waiting)
+        .await
+        .unwrap_or_else(|_| panic!("channel {} did not open", label));
+    errors
+}
+
+#[tokio::test]
+async fn relay_errors_do_not_end_signaling() {
+    let url = start_server();
+    let mut a_signaling = WebSocketSignaling::connect(&url).await.unwrap();
+    a_signaling.join("errors").await.unwrap();
+    let mut b_signaling = WebSocketSignaling::connect(&url).await.unwrap();
+    let peers = b_signaling.join("errors").await.unwrap();
+    let a_id = a_signaling.id().unwrap().to_owned();
+    assert_eq!(peers, vec![a_id.clone()]);
+    let b_id = b_signaling.id().unwrap().to_owned();
+    // the server rejects this, its reply reaches the signaler of `a`
+    a_signaling
+        .send(&SignalMessage::Error {
+            message: "not for clients".to_owned(),
+        })
+        .await
+        .unwrap();
+
+    let config = || ConfigurationBuilder::new().trickle_ice(true).build();
+    let (mut a, mut a_events) = Peer::new_with_stream(config()).await.unwrap();
+    let (mut b, mut b_events) = Peer::new_with_stream(config()).await.unwrap();
+    a.create_channel("first").await.unwrap();
+    a.connect_with(
+        a_signaling.into_signaler(Some(b_id)),
+        NegotiationRole::Impolite,
+    )
+    .await
+    .unwrap();
+    b.connect_with(
+        b_signaling.into_signaler(Some(a_id)),
+        NegotiationRole::Polite,
+    )
+    .await
+    .unwrap();
+    let (errors, ()) = tokio::join!(
+        count_errors_until_open(&mut a_events, "first"),
+        wait_for_channels(&mut b_events, &["first"]),
+    );
+    assert_eq!(errors, 1);
+
+    // renegotiation still goes through the same signalers
+    a.create_channel("later").await.unwrap();
+    tokio::join!(
+        wait_for_channels(&mut a_events, &["later"]),
+        wait_for_channels(&mut b_events, &["later"]),
+    );
+}
+
+#[tokio::test]
+async fn trickle_ice_with_the_server_peer() {
+    let url = start_server();
+    let signaling = WebSocketSignaling::connect(&url).await.unwrap();
+    let config = ConfigurationBuilder::new().trickle_ice(true).build();
+    let (mut peer, mut events) = Peer::new_with_stream(config).await.unwrap();
+    peer.create_channel("hosted").await.unwrap();
+    // the server rejects candidates that arrive before the offer
+    peer.connect_with(signaling.into_signaler(None), NegotiationRole::Impolite)
+        .await
+        .unwrap();
+    wait_for_channels(&mut events, &["hosted"]).await;
+}
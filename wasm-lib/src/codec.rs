@@ -0,0 +1,56 @@
+use std::io::{Read, Write};
+
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use wasm_bindgen::JsValue;
+
+/// Wire format of descriptions and candidates, the counterparts of cyberdeck's `SdpCodec` implementations.
+/// Both sides have to use the same one.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SdpCodec {
+    /// Standard padded base64 of the JSON, cyberdeck's `Base64Codec` and what its signalling server uses by default
+    #[default]
+    Base64,
+    /// The JSON as is, `JsonCodec`
+    Json,
+    /// URL-safe base64 without padding, `UrlSafeBase64Codec`
+    UrlSafeBase64,
+    /// Deflate compressed JSON as URL-safe base64, `DeflateCodec`
+    Deflate,
+}
+
+impl SdpCodec {
+    pub fn encode(&self, json: &str) -> String {
+        match self {
+            SdpCodec::Base64 => STANDARD.encode(json),
+            SdpCodec::Json => json.to_string(),
+            SdpCodec::UrlSafeBase64 => URL_SAFE_NO_PAD.encode(json),
+            SdpCodec::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::best());
+                // writing to a Vec does not fail
+                encoder.write_all(json.as_bytes()).unwrap();
+                URL_SAFE_NO_PAD.encode(encoder.finish().unwrap())
+            }
+        }
+    }
+
+    /// Also takes base64 that is still wrapped in the quotes of a JSON string, as the signalling server answers
+    pub fn decode(&self, encoded: &str) -> Result<String, JsValue> {
+        let base64 = encoded.trim().trim_matches('"');
+        let decoded = match self {
+            SdpCodec::Base64 => STANDARD.decode(base64),
+            SdpCodec::Json => return Ok(encoded.to_string()),
+            SdpCodec::UrlSafeBase64 | SdpCodec::Deflate => URL_SAFE_NO_PAD.decode(base64),
+        }
+        .map_err(|e| JsValue::from_str(&e.to_string()))?;
+
+        match self {
+            SdpCodec::Deflate => {
+                let mut json = String::new();
+                DeflateDecoder::new(&decoded[..]).read_to_string(&mut json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+                Ok(json)
+            }
+            _ => String::from_utf8(decoded).map_err(|e| JsValue::from_str(&e.to_string())),
+        }
+    }
+}
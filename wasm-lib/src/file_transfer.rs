@@ -0,0 +1,267 @@
+use std::{cell::{Cell, RefCell}, collections::VecDeque, rc::Rc};
+
+use js_sys::{Function, Object, Promise, Reflect, Uint8Array, JSON};
+use sha2::{Digest, Sha256};
+use wasm_bindgen::{prelude::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{MessageEvent, RtcDataChannel, RtcDataChannelType};
+
+/// Size of the binary messages carrying file contents, the same as `cyberdeck::FILE_CHUNK_SIZE`
+pub const FILE_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Sending pauses while more than this is buffered and resumes below `LOW_WATER_MARK`
+const HIGH_WATER_MARK: u32 = 1024 * 1024;
+const LOW_WATER_MARK: u32 = 256 * 1024;
+
+type ProgressFn = Box<dyn Fn(u64, u64)>;
+
+/// What a sender announces before the contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    /// Whatever the sender calls the file
+    pub name: String,
+    pub size: u64,
+    /// Lowercase hex of the SHA-256 of the whole file
+    pub sha256: String,
+}
+
+/// Messages that arrived and have not been read yet
+#[derive(Default)]
+struct Inbox {
+    messages: RefCell<VecDeque<JsValue>>,
+    waiting: RefCell<Option<Function>>,
+    closed: Cell<bool>,
+}
+
+impl Inbox {
+    fn push(&self, message: JsValue) {
+        self.messages.borrow_mut().push_back(message);
+        self.wake();
+    }
+
+    fn close(&self) {
+        self.closed.set(true);
+        self.wake();
+    }
+
+    fn wake(&self) {
+        if let Some(resolve) = self.waiting.borrow_mut().take() {
+            let _ = resolve.call0(&JsValue::NULL);
+        }
+    }
+
+    async fn next(&self) -> Option<JsValue> {
+        loop {
+            if let Some(message) = self.messages.borrow_mut().pop_front() {
+                return Some(message);
+            }
+            if self.closed.get() {
+                return None;
+            }
+            let arrived = Promise::new(&mut |resolve, _| *self.waiting.borrow_mut() = Some(resolve));
+            let _ = JsFuture::from(arrived).await;
+        }
+    }
+}
+
+/// One end of a file transfer with a cyberdeck `FileTransfer` on a channel of its own, created by `init_file_transfer`.
+/// Speaks the same protocol, so transfers resume and are verified the same way in both directions.
+pub struct FileTransfer {
+    channel: RtcDataChannel,
+    inbox: Inbox,
+    on_progress: RefCell<Option<ProgressFn>>,
+}
+
+/// Take over the messages of `channel` for file transfers. `onclose` is left alone.
+pub fn init_file_transfer(channel: RtcDataChannel) -> Rc<FileTransfer> {
+    channel.set_binary_type(RtcDataChannelType::Arraybuffer);
+    let transfer = Rc::new(FileTransfer { channel: channel.clone(), inbox: Inbox::default(), on_progress: RefCell::new(None) });
+
+    // the channel holds on to its handlers, they must not keep the transfer alive
+    let weak = Rc::downgrade(&transfer);
+    let onmessage = Closure::<dyn Fn(MessageEvent)>::new(move |event: MessageEvent| {
+        if let Some(transfer) = weak.upgrade() {
+            transfer.inbox.push(event.data());
+        }
+    });
+    channel.set_onmessage(Some(&onmessage.into_js_value().unchecked_into()));
+
+    let weak = Rc::downgrade(&transfer);
+    let onclose = Closure::<dyn Fn()>::new(move || {
+        if let Some(transfer) = weak.upgrade() {
+            transfer.channel.set_onmessage(None);
+            transfer.inbox.close();
+        }
+    });
+    channel.add_event_listener_with_callback("close", onclose.into_js_value().unchecked_ref()).unwrap();
+
+    transfer
+}
+
+impl FileTransfer {
+    /// Called with the bytes transferred so far and the size of the file as chunks are sent or received
+    pub fn set_on_progress(&self, on_progress: impl Fn(u64, u64) + 'static) {
+        *self.on_progress.borrow_mut() = Some(Box::new(on_progress));
+    }
+
+    pub fn channel(&self) -> &RtcDataChannel {
+        &self.channel
+    }
+
+    /// Send `data` as a file called `name`, returns once the receiver verified it
+    pub async fn send_bytes(&self, name: &str, data: &[u8]) -> Result<FileMetadata, JsValue> {
+        let metadata = FileMetadata { name: name.to_string(), size: data.len() as u64, sha256: hex(&Sha256::digest(data)) };
+        let offer = message("offer");
+        Reflect::set(&offer, &"name".into(), &name.into())?;
+        Reflect::set(&offer, &"size".into(), &(metadata.size as f64).into())?;
+        Reflect::set(&offer, &"sha256".into(), &metadata.sha256.as_str().into())?;
+        self.send(&offer)?;
+
+        let accept = self.next_message().await?;
+        let offset = match field(&accept, "type").as_deref() {
+            Some("accept") => Reflect::get(&accept, &"offset".into())?.as_f64().unwrap_or(0.0) as usize,
+            _ => return Err(self.unexpected(&accept)),
+        };
+        if offset > data.len() {
+            return Err(self.fail("offset beyond the end of the file"));
+        }
+
+        self.channel.set_buffered_amount_low_threshold(LOW_WATER_MARK);
+        self.progress(offset as u64, metadata.size);
+        for (index, chunk) in data[offset..].chunks(FILE_CHUNK_SIZE).enumerate() {
+            // the receiver only speaks up early to give up
+            let early = self.inbox.messages.borrow_mut().pop_front();
+            if let Some(text) = early {
+                let message = JSON::parse(&text.as_string().unwrap_or_default()).unwrap_or(JsValue::NULL);
+                return Err(self.unexpected(&message));
+            }
+            while self.channel.buffered_amount() > HIGH_WATER_MARK {
+                let low = Promise::new(&mut |resolve, _| self.channel.set_onbufferedamountlow(Some(&resolve)));
+                JsFuture::from(low).await?;
+            }
+            self.channel.send_with_u8_array(chunk)?;
+            self.progress((offset + index * FILE_CHUNK_SIZE + chunk.len()) as u64, metadata.size);
+        }
+
+        let verified = self.next_message().await?;
+        match field(&verified, "type").as_deref() {
+            Some("verified") => Ok(metadata),
+            _ => Err(self.unexpected(&verified)),
+        }
+    }
+
+    /// Wait for the other side to offer a file
+    pub async fn receive(self: &Rc<Self>) -> Result<IncomingFile, JsValue> {
+        let offer = self.next_message().await?;
+        if field(&offer, "type").as_deref() != Some("offer") {
+            return Err(self.unexpected(&offer));
+        }
+        let metadata = FileMetadata {
+            name: field(&offer, "name").unwrap_or_default(),
+            size: Reflect::get(&offer, &"size".into())?.as_f64().unwrap_or(0.0) as u64,
+            sha256: field(&offer, "sha256").unwrap_or_default().to_lowercase(),
+        };
+        Ok(IncomingFile { transfer: self.clone(), metadata })
+    }
+
+    async fn next_message(&self) -> Result<JsValue, JsValue> {
+        let data = self.inbox.next().await.ok_or_else(|| JsValue::from_str("data channel is closed"))?;
+        match data.as_string() {
+            Some(text) => JSON::parse(&text).map_err(|_| self.fail("invalid message")),
+            None => Err(self.fail("unexpected binary message")),
+        }
+    }
+
+    /// The other side's error, or a new one it is told about
+    fn unexpected(&self, message: &JsValue) -> JsValue {
+        match field(message, "type").as_deref() {
+            Some("error") => field(message, "message").unwrap_or_default().into(),
+            _ => self.fail("unexpected message"),
+        }
+    }
+
+    fn fail(&self, reason: &str) -> JsValue {
+        let error = message("error");
+        let _ = Reflect::set(&error, &"message".into(), &reason.into());
+        let _ = self.send(&error);
+        reason.into()
+    }
+
+    fn send(&self, message: &Object) -> Result<(), JsValue> {
+        let text = JSON::stringify(message)?.as_string().unwrap_or_default();
+        self.channel.send_with_str(&text)
+    }
+
+    fn progress(&self, transferred: u64, size: u64) {
+        if let Some(on_progress) = self.on_progress.borrow().as_ref() {
+            on_progress(transferred, size);
+        }
+    }
+}
+
+/// A file offered by the other side, returned by `FileTransfer::receive`
+pub struct IncomingFile {
+    transfer: Rc<FileTransfer>,
+    metadata: FileMetadata,
+}
+
+impl IncomingFile {
+    pub fn metadata(&self) -> &FileMetadata {
+        &self.metadata
+    }
+
+    /// Receive the file into `data`, resuming after what it already holds from an earlier attempt. If the connection
+    /// is lost, `data` keeps what arrived for the next one. A file that does not match its SHA-256 fails and `data`
+    /// is cleared, so that the next attempt starts from scratch.
+    pub async fn accept(self, data: &mut Vec<u8>) -> Result<FileMetadata, JsValue> {
+        let transfer = &self.transfer;
+        let size = self.metadata.size as usize;
+        if data.len() > size {
+            data.clear();
+        }
+        let accept = message("accept");
+        Reflect::set(&accept, &"offset".into(), &(data.len() as f64).into())?;
+        transfer.send(&accept)?;
+
+        transfer.progress(data.len() as u64, size as u64);
+        while data.len() < size {
+            let chunk = transfer.inbox.next().await.ok_or_else(|| JsValue::from_str("data channel is closed"))?;
+            if let Some(text) = chunk.as_string() {
+                let message = JSON::parse(&text).unwrap_or(JsValue::NULL);
+                return Err(transfer.unexpected(&message));
+            }
+            let chunk = Uint8Array::new(&chunk);
+            if data.len() + chunk.length() as usize > size {
+                return Err(transfer.fail("more data than announced"));
+            }
+            data.extend_from_slice(&chunk.to_vec());
+            transfer.progress(data.len() as u64, size as u64);
+        }
+
+        if hex(&Sha256::digest(&data[..])) != self.metadata.sha256 {
+            data.clear();
+            return Err(transfer.fail("checksum mismatch"));
+        }
+        transfer.send(&message("verified"))?;
+        Ok(self.metadata)
+    }
+
+    /// Turn the file down, the sender fails with `reason`
+    pub fn reject(self, reason: &str) {
+        self.transfer.fail(reason);
+    }
+}
+
+fn message(kind: &str) -> Object {
+    let message = Object::new();
+    Reflect::set(&message, &"type".into(), &kind.into()).unwrap();
+    message
+}
+
+fn field(message: &JsValue, name: &str) -> Option<String> {
+    Reflect::get(message, &name.into()).ok()?.as_string()
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
@@ -0,0 +1,31 @@
+use std::{cell::RefCell, rc::Rc};
+
+use js_sys::Reflect;
+use wasm_bindgen::{prelude::Closure, JsCast, JsValue};
+use web_sys::{RtcDataChannel, RtcPeerConnection};
+
+/// Label of the channel cyberdeck peers send heartbeats on, see `ConfigurationBuilder::heartbeat`
+pub const HEARTBEAT_CHANNEL: &str = "cyberdeck-heartbeat";
+
+/// Answer the heartbeat pings of a cyberdeck peer so it does not consider this side unresponsive.
+/// Listens for the incoming channel without replacing `ondatachannel`, which still sees it.
+pub fn answer_heartbeats(pc: Rc<RefCell<RtcPeerConnection>>) {
+    let ondatachannel = Closure::<dyn Fn(JsValue)>::new(move |event: JsValue| {
+        let channel: RtcDataChannel = Reflect::get(&event, &"channel".into()).unwrap().unchecked_into();
+        if channel.label() != HEARTBEAT_CHANNEL {
+            return;
+        }
+
+        let channel_clone = channel.clone();
+        let onmessage = Closure::<dyn Fn(JsValue)>::new(move |e: JsValue| {
+            let data = Reflect::get(&e, &"data".into()).unwrap().as_string().unwrap_or_default();
+            if let Some(ping) = data.strip_prefix("ping:") {
+                let _ = channel_clone.send_with_str(&format!("pong:{}", ping));
+            }
+        });
+        channel.set_onmessage(Some(&onmessage.into_js_value().unchecked_into()));
+    });
+    pc.borrow()
+        .add_event_listener_with_callback("datachannel", ondatachannel.into_js_value().unchecked_ref())
+        .unwrap();
+}
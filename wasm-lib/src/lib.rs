@@ -4,6 +4,20 @@ use js_sys::{Reflect, JSON, Object, Array, JsString};
 use wasm_bindgen::{prelude::Closure, JsValue, JsCast};
 use web_sys::{Request, RequestInit, RequestMode, Response, RtcPeerConnection, RtcDataChannel, RtcConfiguration, RtcSessionDescriptionInit, window };
 
+mod codec;
+mod file_transfer;
+mod heartbeat;
+mod negotiation;
+mod rpc;
+mod websocket;
+
+pub use codec::SdpCodec;
+pub use file_transfer::{init_file_transfer, FileMetadata, FileTransfer, IncomingFile, FILE_CHUNK_SIZE};
+pub use heartbeat::{answer_heartbeats, HEARTBEAT_CHANNEL};
+pub use negotiation::{init_perfect_negotiation, Negotiation};
+pub use rpc::{init_rpc, RpcChannel, RpcError};
+pub use websocket::{init_websocket_signaling, WebSocketSignaling};
+
 /// Create an RtcPeerConnection with the given ICE/STUN server, defaulting to Google's STUN server
 pub fn create_peer_connection(ice_server: Option<String>) -> Rc<RefCell<RtcPeerConnection>> {
     let mut config = RtcConfiguration::new();
@@ -18,6 +32,11 @@ pub fn create_peer_connection(ice_server: Option<String>) -> Rc<RefCell<RtcPeerC
 
 /// Initialize RtcPeerConnection using selected signalling server endpoint, defaulting to "http://localhost:3000/connect"
 pub async fn init_peer_connection(pc: Rc<RefCell<RtcPeerConnection>>, connect_url: Option<String>, oniceconnectionstatechange: Closure<dyn Fn(JsValue)>) {
+    init_peer_connection_with_codec(pc, connect_url, SdpCodec::Base64, oniceconnectionstatechange).await
+}
+
+/// `init_peer_connection` for a signalling server configured with another `sdp_codec`
+pub async fn init_peer_connection_with_codec(pc: Rc<RefCell<RtcPeerConnection>>, connect_url: Option<String>, codec: SdpCodec, oniceconnectionstatechange: Closure<dyn Fn(JsValue)>) {
     pc.borrow().set_oniceconnectionstatechange(Some(&oniceconnectionstatechange.into_js_value().unchecked_into()));
 
     let pc_clone = pc.clone();
@@ -25,7 +44,7 @@ pub async fn init_peer_connection(pc: Rc<RefCell<RtcPeerConnection>>, connect_ur
 
     let onicecandidate = Closure::<dyn Fn(JsValue)>::new(move |event: JsValue| {    
         if Reflect::get(&event, &"candidate".into()).unwrap().is_null() {
-            let local_description = get_local_description(&pc_clone);
+            let local_description = get_local_description_with_codec(&pc_clone, codec);
             let mut opts = RequestInit::new();
             opts.method("POST");
             opts.mode(RequestMode::Cors);
@@ -44,9 +63,9 @@ pub async fn init_peer_connection(pc: Rc<RefCell<RtcPeerConnection>>, connect_ur
                 let pc_clone_3 = pc_clone_2.clone();
                 let then = Closure::<dyn FnMut(JsValue)>::new(move |answer: JsValue| {
                     let answer: String = answer.unchecked_into::<JsString>().into();
-                    let answer = answer.replace("\"", "");
-                    let atob = window().unwrap().atob(&answer).unwrap();
-                    let parsed = JSON::parse(&atob).unwrap();
+                    // the answer comes as a JSON string
+                    let answer = JSON::parse(&answer).ok().and_then(|answer| answer.as_string()).unwrap_or(answer);
+                    let parsed = JSON::parse(&codec.decode(&answer).unwrap()).unwrap();
                     pc_clone_3.borrow().set_remote_description(
                         &RtcSessionDescriptionInit::unchecked_from_js(parsed)
                     );
@@ -77,6 +96,11 @@ pub async fn init_peer_connection(pc: Rc<RefCell<RtcPeerConnection>>, connect_ur
 
 /// Initialize RtcPeerConnection using offer already known
 pub async fn init_peer_connection_from_offer(pc: Rc<RefCell<RtcPeerConnection>>, offer: String, oniceconnectionstatechange: Closure<dyn Fn(JsValue)>) {
+    init_peer_connection_from_offer_with_codec(pc, offer, SdpCodec::Base64, oniceconnectionstatechange).await
+}
+
+/// `init_peer_connection_from_offer` for an offer in another wire format than base64
+pub async fn init_peer_connection_from_offer_with_codec(pc: Rc<RefCell<RtcPeerConnection>>, offer: String, codec: SdpCodec, oniceconnectionstatechange: Closure<dyn Fn(JsValue)>) {
     pc.borrow().set_oniceconnectionstatechange(Some(&oniceconnectionstatechange.into_js_value().unchecked_into()));
 
     let pc_clone = pc.clone();
@@ -84,9 +108,7 @@ pub async fn init_peer_connection_from_offer(pc: Rc<RefCell<RtcPeerConnection>>,
     let onicecandidate = Closure::<dyn Fn(JsValue)>::new(move |event: JsValue| {    
         if Reflect::get(&event, &"candidate".into()).unwrap().is_null() {
             let pc_clone_2 = pc_clone.clone();
-            let answer: String = offer.replace("\"", "");
-            let atob = window().unwrap().atob(&answer).unwrap();
-            let parsed = JSON::parse(&atob).unwrap();
+            let parsed = JSON::parse(&codec.decode(&offer).unwrap()).unwrap();
             pc_clone_2.borrow().set_remote_description(
                 &RtcSessionDescriptionInit::unchecked_from_js(parsed)
             );
@@ -108,9 +130,13 @@ pub async fn init_peer_connection_from_offer(pc: Rc<RefCell<RtcPeerConnection>>,
 }
 
 pub fn get_local_description(pc: &Rc<RefCell<RtcPeerConnection>>) -> String {
+    get_local_description_with_codec(pc, SdpCodec::Base64)
+}
+
+pub fn get_local_description_with_codec(pc: &Rc<RefCell<RtcPeerConnection>>, codec: SdpCodec) -> String {
     let desc = pc.borrow().local_description().unwrap();
     let desc = &JSON::stringify(&desc.unchecked_into()).unwrap().as_string().unwrap();
-    window().unwrap().btoa(desc).unwrap()
+    codec.encode(desc)
 }
 
 /// Create a data channel using the given RtcPeerConnection, assigned the given label
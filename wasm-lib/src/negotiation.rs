@@ -0,0 +1,155 @@
+use std::{cell::{Cell, RefCell}, rc::Rc};
+
+use js_sys::{Promise, Reflect, JSON};
+use wasm_bindgen::{prelude::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{RtcIceGatheringState, RtcPeerConnection, RtcSignalingState};
+
+use crate::{get_local_description_with_codec, SdpCodec};
+
+/// Perfect negotiation state of an RtcPeerConnection, created by `init_perfect_negotiation` and used by `init_websocket_signaling`
+pub struct Negotiation {
+    pc: Rc<RefCell<RtcPeerConnection>>,
+    polite: Cell<bool>,
+    /// Candidates are sent separately, descriptions go out without waiting for them
+    trickle_ice: bool,
+    making_offer: Cell<bool>,
+    ignore_offer: Cell<bool>,
+    sdp_codec: Cell<SdpCodec>,
+    send_description: SendDescription,
+}
+
+/// Called with "offer" or "answer" and the encoded description
+type SendDescription = Box<dyn Fn(&str, String)>;
+
+/// Negotiate whenever channels or tracks are added, also after the connection is up. Offers and answers are passed to
+/// `send_description` in the same base64 format as the cyberdeck signalling server uses, unless `Negotiation::set_sdp_codec` picks
+/// another one. The other side's go to `Negotiation::receive_description`.
+/// Browsers can roll back their own offer on glare, so they should be the polite side when talking to a native peer.
+pub fn init_perfect_negotiation(pc: Rc<RefCell<RtcPeerConnection>>, polite: bool, send_description: impl Fn(String) + 'static) -> Rc<Negotiation> {
+    let negotiation = Negotiation::new(pc.clone(), polite, false, move |_, description| send_description(description));
+
+    let negotiation_clone = negotiation.clone();
+    let onnegotiationneeded = Closure::<dyn Fn()>::new(move || {
+        let negotiation = negotiation_clone.clone();
+        spawn_local(async move {
+            let _ = negotiation.make_offer().await;
+        });
+    });
+    pc.borrow().set_onnegotiationneeded(Some(&onnegotiationneeded.into_js_value().unchecked_into()));
+
+    negotiation
+}
+
+impl Negotiation {
+    /// Negotiation without an onnegotiationneeded handler, whoever creates it decides when to `make_offer`
+    pub(crate) fn new(pc: Rc<RefCell<RtcPeerConnection>>, polite: bool, trickle_ice: bool, send_description: impl Fn(&str, String) + 'static) -> Rc<Negotiation> {
+        Rc::new(Negotiation {
+            pc,
+            polite: Cell::new(polite),
+            trickle_ice,
+            making_offer: Cell::new(false),
+            ignore_offer: Cell::new(false),
+            sdp_codec: Cell::new(SdpCodec::Base64),
+            send_description: Box::new(send_description),
+        })
+    }
+
+    pub(crate) fn set_polite(&self, polite: bool) {
+        self.polite.set(polite);
+    }
+
+    pub(crate) fn sdp_codec(&self) -> SdpCodec {
+        self.sdp_codec.get()
+    }
+
+    /// Wire format of the descriptions, it has to match the `sdp_codec` of the other side
+    pub fn set_sdp_codec(&self, codec: SdpCodec) {
+        self.sdp_codec.set(codec);
+    }
+
+    pub(crate) async fn make_offer(&self) -> Result<(), JsValue> {
+        self.making_offer.set(true);
+        let result = async {
+            let offer = self.pc.borrow().create_offer();
+            let offer = JsFuture::from(offer).await?;
+            let set_local = self.pc.borrow().set_local_description(offer.unchecked_ref());
+            JsFuture::from(set_local).await?;
+            self.send_local_description("offer").await
+        }.await;
+        self.making_offer.set(false);
+        result
+    }
+
+    /// Apply an offer or answer from the other side, offers are answered through `send_description`
+    pub async fn receive_description(&self, description: String) -> Result<(), JsValue> {
+        let description = JSON::parse(&self.sdp_codec.get().decode(&description)?)?;
+        let is_offer = Reflect::get(&description, &"type".into())?.as_string().as_deref() == Some("offer");
+
+        let offer_collision = is_offer && (self.making_offer.get() || self.pc.borrow().signaling_state() != RtcSignalingState::Stable);
+        self.ignore_offer.set(!self.polite.get() && offer_collision);
+        if self.ignore_offer.get() {
+            return Ok(());
+        }
+
+        // a pending local offer is rolled back implicitly by the browser
+        let set_remote = self.pc.borrow().set_remote_description(description.unchecked_ref());
+        JsFuture::from(set_remote).await?;
+        if is_offer {
+            let answer = self.pc.borrow().create_answer();
+            let answer = JsFuture::from(answer).await?;
+            let set_local = self.pc.borrow().set_local_description(answer.unchecked_ref());
+            JsFuture::from(set_local).await?;
+            self.send_local_description("answer").await?;
+        }
+        Ok(())
+    }
+
+    async fn send_local_description(&self, kind: &str) -> Result<(), JsValue> {
+        if !self.trickle_ice {
+            wait_for_ice_gathering(&self.pc).await?;
+        }
+        (self.send_description)(kind, get_local_description_with_codec(&self.pc, self.sdp_codec.get()));
+        Ok(())
+    }
+
+    /// Whether the last remote offer was ignored because of glare, candidates for it are expected to fail
+    pub fn ignoring_offer(&self) -> bool {
+        self.ignore_offer.get()
+    }
+}
+
+/// Resolves once all local ICE candidates are in the local description
+async fn wait_for_ice_gathering(pc: &Rc<RefCell<RtcPeerConnection>>) -> Result<(), JsValue> {
+    if pc.borrow().ice_gathering_state() == RtcIceGatheringState::Complete {
+        return Ok(());
+    }
+
+    let mut listener = None;
+    let gathered = Promise::new(&mut |resolve, _reject| {
+        let pc_clone = pc.clone();
+        let onicegatheringstatechange = Closure::<dyn Fn()>::new(move || {
+            if pc_clone.borrow().ice_gathering_state() == RtcIceGatheringState::Complete {
+                let _ = resolve.call0(&JsValue::NULL);
+            }
+        });
+        let _ = pc.borrow().add_event_listener_with_callback("icegatheringstatechange", onicegatheringstatechange.as_ref().unchecked_ref());
+        listener = Some(GatheringListener { pc: pc.clone(), closure: onicegatheringstatechange });
+    });
+    // removed again when the wait is over, also if it is dropped before gathering completes
+    let _listener = listener;
+    JsFuture::from(gathered).await?;
+    Ok(())
+}
+
+/// An `icegatheringstatechange` listener that is removed from the connection when dropped
+struct GatheringListener {
+    pc: Rc<RefCell<RtcPeerConnection>>,
+    closure: Closure<dyn Fn()>,
+}
+
+impl Drop for GatheringListener {
+    fn drop(&mut self) {
+        let _ = self.pc.borrow().remove_event_listener_with_callback("icegatheringstatechange", self.closure.as_ref().unchecked_ref());
+    }
+}
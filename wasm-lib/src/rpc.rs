@@ -0,0 +1,232 @@
+use std::{cell::{Cell, RefCell}, collections::{HashMap, HashSet}, future::Future, pin::Pin, rc::Rc};
+
+use js_sys::{Function, Object, Promise, Reflect, JSON};
+use wasm_bindgen::{prelude::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{window, MessageEvent, RtcDataChannel};
+
+type Handler = Rc<dyn Fn(JsValue) -> Pin<Box<dyn Future<Output = Result<JsValue, RpcError>>>>>;
+
+/// Error reply of a call, the same as `cyberdeck::rpc::RpcError`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub const METHOD_NOT_FOUND: i64 = -32601;
+    pub const INVALID_PARAMS: i64 = -32602;
+    pub const INTERNAL_ERROR: i64 = -32603;
+    /// Never sent, the call got no response in time
+    pub const TIMEOUT: i64 = -32000;
+    /// Never sent, the channel closed or was not open
+    pub const CLOSED: i64 = -32001;
+
+    pub fn new(code: i64, message: impl Into<String>) -> RpcError {
+        RpcError { code, message: message.into() }
+    }
+
+    fn to_js(&self) -> JsValue {
+        let error = Object::new();
+        Reflect::set(&error, &"code".into(), &(self.code as f64).into()).unwrap();
+        Reflect::set(&error, &"message".into(), &self.message.as_str().into()).unwrap();
+        error.into()
+    }
+
+    fn from_js(error: &JsValue) -> RpcError {
+        let field = |name: &str| Reflect::get(error, &name.into()).unwrap_or(JsValue::UNDEFINED);
+        RpcError {
+            code: field("code").as_f64().map(|code| code as i64).unwrap_or(RpcError::INTERNAL_ERROR),
+            message: field("message").as_string().unwrap_or_default(),
+        }
+    }
+}
+
+/// Calls between this side and a cyberdeck `RpcChannel` on a data channel, created by `init_rpc`.
+/// Uses the same JSON messages, so either side can call methods of the other.
+pub struct RpcChannel {
+    channel: RtcDataChannel,
+    next_id: Cell<u64>,
+    timeout_ms: Cell<i32>,
+    closed: Cell<bool>,
+    /// resolve and reject of calls waiting for their response
+    pending: RefCell<HashMap<u64, (Function, Function)>>,
+    methods: RefCell<HashMap<String, Handler>>,
+    /// Requests of the other side still being handled, cancelled ones are removed and get no response
+    running: RefCell<HashSet<u64>>,
+}
+
+/// Take over the messages of `channel` for calls, with a timeout of 30 seconds. `onclose` is left alone.
+pub fn init_rpc(channel: RtcDataChannel) -> Rc<RpcChannel> {
+    let rpc = Rc::new(RpcChannel {
+        channel: channel.clone(),
+        next_id: Cell::new(1),
+        timeout_ms: Cell::new(30_000),
+        closed: Cell::new(false),
+        pending: RefCell::new(HashMap::new()),
+        methods: RefCell::new(HashMap::new()),
+        running: RefCell::new(HashSet::new()),
+    });
+
+    // the channel holds on to its handlers, they must not keep the RpcChannel alive
+    let weak = Rc::downgrade(&rpc);
+    let onmessage = Closure::<dyn Fn(MessageEvent)>::new(move |event: MessageEvent| {
+        let Some(rpc) = weak.upgrade() else { return };
+        let Some(text) = event.data().as_string() else { return };
+        let Ok(message) = JSON::parse(&text) else { return };
+        rpc.receive(message);
+    });
+    channel.set_onmessage(Some(&onmessage.into_js_value().unchecked_into()));
+
+    let weak = Rc::downgrade(&rpc);
+    let onclose = Closure::<dyn Fn()>::new(move || {
+        if let Some(rpc) = weak.upgrade() {
+            rpc.close();
+        }
+    });
+    channel.add_event_listener_with_callback("close", onclose.into_js_value().unchecked_ref()).unwrap();
+
+    rpc
+}
+
+impl RpcChannel {
+    /// Answer calls of `name` from the other side
+    pub fn method<F>(&self, name: &str, handler: impl Fn(JsValue) -> F + 'static)
+    where
+        F: Future<Output = Result<JsValue, RpcError>> + 'static,
+    {
+        let handler: Handler = Rc::new(move |params| Box::pin(handler(params)));
+        self.methods.borrow_mut().insert(name.to_string(), handler);
+    }
+
+    /// Timeout of `call` in milliseconds
+    pub fn set_timeout(&self, timeout_ms: i32) {
+        self.timeout_ms.set(timeout_ms);
+    }
+
+    pub async fn call(&self, method: &str, params: &JsValue) -> Result<JsValue, RpcError> {
+        self.call_with_timeout(method, params, self.timeout_ms.get()).await
+    }
+
+    /// Like `call` but with its own timeout, after which the call is cancelled. Dropping the
+    /// future cancels the call as well.
+    pub async fn call_with_timeout(&self, method: &str, params: &JsValue, timeout_ms: i32) -> Result<JsValue, RpcError> {
+        if self.closed.get() {
+            return Err(RpcError::new(RpcError::CLOSED, "data channel is closed"));
+        }
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+
+        let mut settle = None;
+        let response = Promise::new(&mut |resolve, reject| settle = Some((resolve, reject)));
+        let (resolve, reject) = settle.unwrap();
+        self.pending.borrow_mut().insert(id, (resolve, reject.clone()));
+        let _call = PendingCall { rpc: self, id };
+
+        let request = message("request", id);
+        Reflect::set(&request, &"method".into(), &method.into()).unwrap();
+        Reflect::set(&request, &"params".into(), params).unwrap();
+        self.send(&request).map_err(|_| RpcError::new(RpcError::CLOSED, "data channel is not open"))?;
+
+        let ontimeout = Closure::once_into_js(move || {
+            let _ = reject.call1(&JsValue::NULL, &RpcError::new(RpcError::TIMEOUT, "timed out").to_js());
+        });
+        let timer = window().unwrap()
+            .set_timeout_with_callback_and_timeout_and_arguments_0(ontimeout.unchecked_ref(), timeout_ms)
+            .unwrap();
+        let result = JsFuture::from(response).await;
+        window().unwrap().clear_timeout_with_handle(timer);
+        result.map_err(|e| RpcError::from_js(&e))
+    }
+
+    pub fn channel(&self) -> &RtcDataChannel {
+        &self.channel
+    }
+
+    fn receive(self: &Rc<Self>, message: JsValue) {
+        let field = |name: &str| Reflect::get(&message, &name.into()).unwrap_or(JsValue::UNDEFINED);
+        let Some(id) = field("id").as_f64().map(|id| id as u64) else { return };
+
+        match field("type").as_string().as_deref() {
+            Some("request") => {
+                let method = field("method").as_string().unwrap_or_default();
+                let handler = self.methods.borrow().get(&method).cloned();
+                let Some(handler) = handler else {
+                    self.respond(id, Err(RpcError::new(RpcError::METHOD_NOT_FOUND, format!("unknown method {}", method))));
+                    return;
+                };
+                let params = field("params");
+                let params = if params.is_undefined() { JsValue::NULL } else { params };
+                self.running.borrow_mut().insert(id);
+                let rpc = self.clone();
+                spawn_local(async move {
+                    let result = handler(params).await;
+                    if rpc.running.borrow_mut().remove(&id) {
+                        rpc.respond(id, result);
+                    }
+                });
+            }
+            Some("response") => {
+                let Some((resolve, reject)) = self.pending.borrow_mut().remove(&id) else { return };
+                let error = field("error");
+                let _ = if error.is_undefined() || error.is_null() {
+                    let result = field("result");
+                    let result = if result.is_undefined() { JsValue::NULL } else { result };
+                    resolve.call1(&JsValue::NULL, &result)
+                } else {
+                    reject.call1(&JsValue::NULL, &error)
+                };
+            }
+            Some("cancel") => {
+                self.running.borrow_mut().remove(&id);
+            }
+            _ => {}
+        }
+    }
+
+    fn respond(&self, id: u64, result: Result<JsValue, RpcError>) {
+        let response = message("response", id);
+        match result {
+            Ok(result) => Reflect::set(&response, &"result".into(), &result).unwrap(),
+            Err(error) => Reflect::set(&response, &"error".into(), &error.to_js()).unwrap(),
+        };
+        let _ = self.send(&response);
+    }
+
+    fn send(&self, message: &Object) -> Result<(), JsValue> {
+        let text = JSON::stringify(message)?.as_string().unwrap_or_default();
+        self.channel.send_with_str(&text)
+    }
+
+    fn close(&self) {
+        self.closed.set(true);
+        self.channel.set_onmessage(None);
+        self.running.borrow_mut().clear();
+        let closed = RpcError::new(RpcError::CLOSED, "data channel is closed").to_js();
+        for (_, (_, reject)) in self.pending.borrow_mut().drain() {
+            let _ = reject.call1(&JsValue::NULL, &closed);
+        }
+    }
+}
+
+/// Removes a call that ends without a response, on timeout or when dropped, and tells the other side
+struct PendingCall<'a> {
+    rpc: &'a RpcChannel,
+    id: u64,
+}
+
+impl Drop for PendingCall<'_> {
+    fn drop(&mut self) {
+        if self.rpc.pending.borrow_mut().remove(&self.id).is_some() && !self.rpc.closed.get() {
+            let _ = self.rpc.send(&message("cancel", self.id));
+        }
+    }
+}
+
+fn message(kind: &str, id: u64) -> Object {
+    let message = Object::new();
+    Reflect::set(&message, &"type".into(), &kind.into()).unwrap();
+    Reflect::set(&message, &"id".into(), &(id as f64).into()).unwrap();
+    message
+}
@@ -0,0 +1,194 @@
+use std::{cell::{Cell, RefCell}, rc::{Rc, Weak}};
+
+use js_sys::{Array, Object, Reflect, JSON};
+use wasm_bindgen::{prelude::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{console, MessageEvent, RtcIceCandidateInit, RtcPeerConnection, RtcPeerConnectionIceEvent, WebSocket};
+
+use crate::{Negotiation, SdpCodec};
+
+/// Signalling state of an RtcPeerConnection connected through the cyberdeck WebSocket endpoint, created by `init_websocket_signaling`
+pub struct WebSocketSignaling {
+    ws: WebSocket,
+    pc: Rc<RefCell<RtcPeerConnection>>,
+    room: Option<String>,
+    id: RefCell<Option<String>>,
+    /// Id of the peer we talk to, null for the peer hosted by the server, unknown while waiting in a room
+    remote: RefCell<Option<JsValue>>,
+    negotiation: Rc<Negotiation>,
+    /// Negotiation was needed before there was anyone to send the offer to
+    offer_pending: Cell<bool>,
+}
+
+/// Connect `pc` through the cyberdeck WebSocket signalling endpoint, defaulting to "ws://localhost:3000/ws".
+/// With a `room` it connects to the first browser or native peer already in it or the next one to join, without one to
+/// a peer hosted by the server. Offers, answers and later renegotiations go over the socket, candidates are trickled as they are found.
+pub fn init_websocket_signaling(pc: Rc<RefCell<RtcPeerConnection>>, ws_url: Option<String>, room: Option<String>) -> Result<Rc<WebSocketSignaling>, JsValue> {
+    let ws = WebSocket::new(&ws_url.unwrap_or("ws://localhost:3000/ws".to_string()))?;
+    let signaling = Rc::new_cyclic(|signaling: &Weak<WebSocketSignaling>| {
+        let signaling = signaling.clone();
+        // browsers can roll back their offer, the newcomer of a room becomes impolite instead
+        let negotiation = Negotiation::new(pc.clone(), true, true, move |kind, description| {
+            if let Some(signaling) = signaling.upgrade() {
+                signaling.send_to_remote(kind, "sdp", &description);
+            }
+        });
+        WebSocketSignaling {
+            ws: ws.clone(),
+            pc: pc.clone(),
+            room,
+            id: RefCell::new(None),
+            remote: RefCell::new(None),
+            negotiation,
+            offer_pending: Cell::new(false),
+        }
+    });
+
+    let signaling_clone = signaling.clone();
+    let onopen = Closure::<dyn Fn()>::new(move || {
+        match &signaling_clone.room {
+            Some(room) => signaling_clone.send(&message("join", &[("room", room.into())])),
+            None => signaling_clone.set_remote(JsValue::NULL),
+        }
+    });
+    ws.set_onopen(Some(&onopen.into_js_value().unchecked_into()));
+
+    let signaling_clone = signaling.clone();
+    let onmessage = Closure::<dyn Fn(MessageEvent)>::new(move |event: MessageEvent| {
+        let Some(text) = event.data().as_string() else { return };
+        let Ok(message) = JSON::parse(&text) else { return };
+        let signaling = signaling_clone.clone();
+        spawn_local(async move {
+            if let Err(e) = signaling.handle(message).await {
+                console::warn_2(&"cyberdeck signalling:".into(), &e);
+            }
+        });
+    });
+    ws.set_onmessage(Some(&onmessage.into_js_value().unchecked_into()));
+
+    let signaling_clone = signaling.clone();
+    let onicecandidate = Closure::<dyn Fn(RtcPeerConnectionIceEvent)>::new(move |event: RtcPeerConnectionIceEvent| {
+        if let Some(candidate) = event.candidate() {
+            let candidate = JSON::stringify(&candidate.to_json()).unwrap().as_string().unwrap();
+            let candidate = signaling_clone.negotiation.sdp_codec().encode(&candidate);
+            signaling_clone.send_to_remote("candidate", "candidate", &candidate);
+        }
+    });
+    pc.borrow().set_onicecandidate(Some(&onicecandidate.into_js_value().unchecked_into()));
+
+    let signaling_clone = signaling.clone();
+    let onnegotiationneeded = Closure::<dyn Fn()>::new(move || {
+        let signaling = signaling_clone.clone();
+        spawn_local(async move {
+            let _ = signaling.make_offer().await;
+        });
+    });
+    pc.borrow().set_onnegotiationneeded(Some(&onnegotiationneeded.into_js_value().unchecked_into()));
+
+    Ok(signaling)
+}
+
+impl WebSocketSignaling {
+    /// Id the server assigned to this connection, known once the room was joined
+    pub fn id(&self) -> Option<String> {
+        self.id.borrow().clone()
+    }
+
+    /// Id of the room member this connection negotiates with, if one has shown up yet
+    pub fn remote_id(&self) -> Option<String> {
+        self.remote.borrow().as_ref().and_then(|remote| remote.as_string())
+    }
+
+    /// Wire format of descriptions and candidates, it has to match the `sdp_codec` of the server and the other peers
+    pub fn set_sdp_codec(&self, codec: SdpCodec) {
+        self.negotiation.set_sdp_codec(codec);
+    }
+
+    pub fn close(&self) {
+        let _ = self.ws.close();
+    }
+
+    async fn handle(self: &Rc<Self>, message: JsValue) -> Result<(), JsValue> {
+        let field = |name: &str| Reflect::get(&message, &name.into());
+        let from = field("peer")?;
+        let from = if from.is_undefined() { JsValue::NULL } else { from };
+
+        match field("type")?.as_string().as_deref() {
+            Some("joined") => {
+                *self.id.borrow_mut() = field("id")?.as_string();
+                let peers: Array = field("peers")?.unchecked_into();
+                if peers.length() > 0 {
+                    self.negotiation.set_polite(false);
+                    self.set_remote(peers.get(0));
+                }
+            }
+            Some("peer_joined") if self.remote.borrow().is_none() => self.set_remote(field("id")?),
+            Some("peer_left") if self.remote.borrow().as_ref() == Some(&field("id")?) => {
+                *self.remote.borrow_mut() = None;
+            }
+            Some("offer") | Some("answer") => {
+                if self.remote.borrow().is_none() {
+                    *self.remote.borrow_mut() = Some(from.clone());
+                }
+                if self.remote.borrow().as_ref() == Some(&from) {
+                    self.negotiation.receive_description(field("sdp")?.as_string().unwrap_or_default()).await?;
+                }
+            }
+            Some("candidate") if self.remote.borrow().as_ref() == Some(&from) => {
+                let candidate = self.negotiation.sdp_codec().decode(&field("candidate")?.as_string().unwrap_or_default())?;
+                let candidate: RtcIceCandidateInit = JSON::parse(&candidate)?.unchecked_into();
+                let added = self.pc.borrow().add_ice_candidate_with_opt_rtc_ice_candidate_init(Some(&candidate));
+                let added = JsFuture::from(added).await;
+                if added.is_err() && !self.negotiation.ignoring_offer() {
+                    added?;
+                }
+            }
+            Some("error") => return Err(field("message")?),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn set_remote(self: &Rc<Self>, remote: JsValue) {
+        *self.remote.borrow_mut() = Some(remote);
+        if self.offer_pending.replace(false) {
+            // the negotiationneeded event is not repeated, offer what was added so far
+            let signaling = self.clone();
+            spawn_local(async move {
+                let _ = signaling.make_offer().await;
+            });
+        }
+    }
+
+    async fn make_offer(&self) -> Result<(), JsValue> {
+        if self.remote.borrow().is_none() {
+            self.offer_pending.set(true);
+            return Ok(());
+        }
+        self.negotiation.make_offer().await
+    }
+
+    fn send_to_remote(&self, kind: &str, key: &str, value: &str) {
+        let Some(remote) = self.remote.borrow().clone() else { return };
+        let mut fields = vec![(key, JsValue::from(value))];
+        if !remote.is_null() {
+            fields.push(("peer", remote));
+        }
+        self.send(&message(kind, &fields));
+    }
+
+    fn send(&self, message: &JsValue) {
+        if let Ok(text) = JSON::stringify(message) {
+            let _ = self.ws.send_with_str(&String::from(text));
+        }
+    }
+}
+
+fn message(kind: &str, fields: &[(&str, JsValue)]) -> JsValue {
+    let message = Object::new();
+    let _ = Reflect::set(&message, &"type".into(), &kind.into());
+    for (key, value) in fields {
+        let _ = Reflect::set(&message, &(*key).into(), value);
+    }
+    message.into()
+}